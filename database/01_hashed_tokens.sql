@@ -0,0 +1,9 @@
+-- Player tokens are now stored as a keyed hash (see player_token_pepper),
+-- plaintext tokens are upgraded on their first successful authentication
+ALTER TABLE player_tokens DROP CONSTRAINT player_tokens_pkey;
+ALTER TABLE player_tokens ADD COLUMN id SERIAL NOT NULL PRIMARY KEY;
+ALTER TABLE player_tokens ALTER COLUMN token DROP NOT NULL;
+ALTER TABLE player_tokens ADD COLUMN token_hash bytea;
+ALTER TABLE player_tokens ADD UNIQUE (token);
+ALTER TABLE player_tokens ADD UNIQUE (token_hash);
+ALTER TABLE player_tokens ADD CONSTRAINT "Token is stored" CHECK ((token IS NOT NULL) OR (token_hash IS NOT NULL));
@@ -0,0 +1,6 @@
+-- Players can link several devices, each one having its own token
+ALTER TABLE player_tokens ADD COLUMN device_name character varying(64);
+ALTER TABLE player_tokens ADD COLUMN creation_time timestamp without time zone NOT NULL DEFAULT NOW();
+ALTER TABLE player_tokens ADD COLUMN last_used_at timestamp without time zone;
+
+CREATE INDEX player_tokens_player_id ON player_tokens USING btree (player_id);
@@ -0,0 +1,2 @@
+-- Player tokens can expire (see player_token_duration and player_token_idle_expiry)
+ALTER TABLE player_tokens ADD COLUMN expires_at timestamp without time zone;
@@ -0,0 +1,16 @@
+CREATE TABLE player_nickname_history (
+    id SERIAL NOT NULL,
+    player_id integer NOT NULL,
+    nickname character varying(16) NOT NULL,
+    change_time timestamp without time zone NOT NULL,
+    reserved_until timestamp without time zone,
+    PRIMARY KEY (id),
+    FOREIGN KEY (player_id)
+        REFERENCES players (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+        NOT VALID
+);
+
+CREATE INDEX player_nickname_history_player_id ON player_nickname_history USING btree (player_id, change_time);
+CREATE INDEX player_nickname_history_nickname ON player_nickname_history USING btree (nickname);
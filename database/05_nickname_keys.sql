@@ -0,0 +1,27 @@
+-- Nicknames are unique regardless of their case and of lookalike characters,
+-- their length is checked by the API as it counts graphemes instead of characters
+ALTER TABLE players ALTER COLUMN nickname TYPE character varying;
+ALTER TABLE player_nickname_history ALTER COLUMN nickname TYPE character varying;
+
+-- the key is the skeleton of the lowercased nickname, it only changes 0, 1 and m in ascii nicknames,
+-- the other nicknames are left without a key and keyed by the API when it starts
+ALTER TABLE players ADD COLUMN nickname_key character varying;
+UPDATE players SET nickname_key = REPLACE(REPLACE(REPLACE(LOWER(nickname), 'm', 'rn'), '0', 'o'), '1', 'l')
+    WHERE nickname ~ '^[A-Za-z0-9 _]*$';
+
+-- nicknames sharing a key were allowed, the one already equal to its key or else the oldest keeps it,
+-- the others are keyed by their nickname (never the key of another one) until they are renamed
+UPDATE players SET nickname_key = players.nickname
+    FROM (
+        SELECT id, ROW_NUMBER() OVER (PARTITION BY nickname_key ORDER BY nickname = nickname_key DESC, id) AS key_rank
+        FROM players
+        WHERE nickname_key IS NOT NULL
+    ) AS ranked_players
+    WHERE players.id = ranked_players.id AND ranked_players.key_rank > 1;
+CREATE UNIQUE INDEX players_nickname_key_unique ON players USING btree (nickname_key);
+
+ALTER TABLE player_nickname_history ADD COLUMN nickname_key character varying;
+UPDATE player_nickname_history SET nickname_key = REPLACE(REPLACE(REPLACE(LOWER(nickname), 'm', 'rn'), '0', 'o'), '1', 'l')
+    WHERE nickname ~ '^[A-Za-z0-9 _]*$';
+DROP INDEX player_nickname_history_nickname;
+CREATE INDEX player_nickname_history_nickname_key ON player_nickname_history USING btree (nickname_key);
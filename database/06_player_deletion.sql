@@ -0,0 +1,4 @@
+-- Players are deleted once player_deletion_grace_period has elapsed since their deletion request
+ALTER TABLE players ADD COLUMN deletion_time timestamp without time zone;
+
+CREATE INDEX players_deletion_time ON players USING btree (deletion_time) WHERE deletion_time IS NOT NULL;
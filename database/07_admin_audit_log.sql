@@ -0,0 +1,12 @@
+CREATE TABLE admin_audit_log (
+    id SERIAL NOT NULL,
+    time timestamp without time zone NOT NULL,
+    actor character varying NOT NULL,
+    action character varying NOT NULL,
+    player_id integer,
+    details jsonb NOT NULL,
+    PRIMARY KEY (id)
+);
+
+-- no foreign key on player_id, the log must outlive deleted players
+CREATE INDEX admin_audit_log_player_id ON admin_audit_log USING btree (player_id);
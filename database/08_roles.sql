@@ -0,0 +1,35 @@
+-- A player can have several permissions, either directly or through its roles
+ALTER TABLE player_permissions DROP CONSTRAINT player_permissions_pkey;
+ALTER TABLE player_permissions DROP CONSTRAINT player_permissions_player_id_permission_key;
+ALTER TABLE player_permissions ADD PRIMARY KEY (player_id, permission);
+
+CREATE TABLE roles (
+    id SERIAL NOT NULL,
+    name character varying NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE (name)
+);
+
+CREATE TABLE role_permissions (
+    role_id integer NOT NULL,
+    permission character varying NOT NULL,
+    PRIMARY KEY (role_id, permission),
+    FOREIGN KEY (role_id)
+        REFERENCES roles (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+CREATE TABLE player_roles (
+    player_id integer NOT NULL,
+    role_id integer NOT NULL,
+    PRIMARY KEY (player_id, role_id),
+    FOREIGN KEY (player_id)
+        REFERENCES players (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE,
+    FOREIGN KEY (role_id)
+        REFERENCES roles (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
@@ -0,0 +1,15 @@
+CREATE TABLE player_bans (
+    id SERIAL NOT NULL,
+    player_id integer NOT NULL,
+    reason character varying NOT NULL,
+    issuer character varying NOT NULL,
+    start_time timestamp without time zone NOT NULL,
+    end_time timestamp without time zone,
+    PRIMARY KEY (id),
+    FOREIGN KEY (player_id)
+        REFERENCES players (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+CREATE INDEX player_bans_player_id ON player_bans USING btree (player_id);
@@ -0,0 +1,7 @@
+-- Used to limit the number of players created per IP, rows older than a day are removed
+CREATE TABLE player_creations (
+    ip inet NOT NULL,
+    creation_time timestamp without time zone NOT NULL
+);
+
+CREATE INDEX player_creations_ip ON player_creations USING btree (ip, creation_time);
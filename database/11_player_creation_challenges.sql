@@ -0,0 +1,8 @@
+-- Solved player creation challenges, kept until their expiration to prevent their reuse
+CREATE TABLE player_creation_challenges (
+    nonce bytea NOT NULL,
+    expire_timestamp bigint NOT NULL,
+    PRIMARY KEY (nonce)
+);
+
+CREATE INDEX player_creation_challenges_expire_timestamp ON player_creation_challenges USING btree (expire_timestamp);
@@ -0,0 +1,14 @@
+CREATE TABLE player_recovery_codes (
+    id SERIAL NOT NULL,
+    player_id integer NOT NULL,
+    code_hash bytea NOT NULL,
+    creation_time timestamp without time zone NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE (code_hash),
+    FOREIGN KEY (player_id)
+        REFERENCES players (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+CREATE INDEX player_recovery_codes_player_id ON player_recovery_codes USING btree (player_id);
@@ -0,0 +1,31 @@
+CREATE TABLE player_identities (
+    id SERIAL NOT NULL,
+    player_id integer NOT NULL,
+    issuer character varying NOT NULL,
+    subject character varying NOT NULL,
+    creation_time timestamp without time zone NOT NULL,
+    last_used_at timestamp without time zone,
+    PRIMARY KEY (id),
+    UNIQUE (issuer, subject),
+    FOREIGN KEY (player_id)
+        REFERENCES players (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+CREATE INDEX player_identities_player_id ON player_identities USING btree (player_id);
+
+-- Logins started with /v1/oidc/authorize, removed once finished or expired
+CREATE TABLE oidc_logins (
+    state character varying NOT NULL,
+    nonce character varying NOT NULL,
+    code_verifier character varying NOT NULL,
+    player_id integer,
+    nickname character varying,
+    expires_at timestamp without time zone NOT NULL,
+    PRIMARY KEY (state),
+    FOREIGN KEY (player_id)
+        REFERENCES players (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
@@ -0,0 +1,17 @@
+-- Refresh tokens of the game API, each refresh rotates the token and marks the previous one
+-- A family is started by each game connection, reusing a rotated token revokes its whole family
+CREATE TABLE game_refresh_tokens (
+    jti uuid NOT NULL,
+    family_id uuid NOT NULL,
+    player_id integer NOT NULL,
+    expires_at timestamp without time zone NOT NULL,
+    rotated_at timestamp without time zone,
+    PRIMARY KEY (jti),
+    FOREIGN KEY (player_id)
+        REFERENCES players (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+CREATE INDEX game_refresh_tokens_family_id ON game_refresh_tokens USING btree (family_id);
+CREATE INDEX game_refresh_tokens_player_id ON game_refresh_tokens USING btree (player_id);
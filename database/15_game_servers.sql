@@ -0,0 +1,11 @@
+-- Game servers allowed to use the game server API, only the hash of their key is stored
+CREATE TABLE game_servers (
+    id SERIAL NOT NULL,
+    name character varying NOT NULL,
+    key_hash bytea NOT NULL,
+    creation_time timestamp without time zone NOT NULL,
+    last_used_at timestamp without time zone,
+    PRIMARY KEY (id),
+    UNIQUE (name),
+    UNIQUE (key_hash)
+);
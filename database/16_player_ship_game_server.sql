@@ -0,0 +1,7 @@
+-- Game server which wrote the ship last, ships written before game servers were authenticated have none
+ALTER TABLE player_ships ADD COLUMN last_update_game_server_id integer;
+
+ALTER TABLE player_ships ADD FOREIGN KEY (last_update_game_server_id)
+    REFERENCES game_servers (id) MATCH SIMPLE
+    ON UPDATE CASCADE
+    ON DELETE SET NULL;
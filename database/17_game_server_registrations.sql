@@ -0,0 +1,20 @@
+-- Running game servers, registered with /game_server/v1/register and kept alive by heartbeats
+CREATE TABLE game_server_registrations (
+    game_server_id integer NOT NULL,
+    address character varying NOT NULL,
+    port integer NOT NULL,
+    region character varying NOT NULL,
+    capacity integer NOT NULL,
+    version character varying NOT NULL,
+    player_count integer NOT NULL,
+    registration_time timestamp without time zone NOT NULL,
+    last_heartbeat timestamp without time zone NOT NULL,
+    connection_token_key bytea NOT NULL,
+    PRIMARY KEY (game_server_id),
+    FOREIGN KEY (game_server_id)
+        REFERENCES game_servers (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+CREATE INDEX game_server_registrations_last_heartbeat ON game_server_registrations USING btree (last_heartbeat);
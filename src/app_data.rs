@@ -1,10 +1,20 @@
+use std::sync::atomic::AtomicU8;
+
 use cached::TimedCache;
 use tokio::sync::Mutex;
 
 use crate::fetcher::Fetcher;
+use crate::game_api_keys::GameApiKeys;
+use crate::nickname::blocklist::NicknameBlocklist;
+use crate::oidc::OidcClient;
 use crate::routes::version::CachedReleased;
 
 pub struct AppData {
     pub cache: Mutex<TimedCache<&'static str, CachedReleased>>,
     pub fetcher: Fetcher,
+    pub nickname_blocklist: NicknameBlocklist,
+    /// initialized from `player_creation_challenge_difficulty`, can be changed at runtime
+    pub challenge_difficulty: AtomicU8,
+    pub oidc: Option<OidcClient>,
+    pub game_api_keys: GameApiKeys,
 }
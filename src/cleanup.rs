@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+use actix_web::web;
+use deadpool_postgres::tokio_postgres::types::Type;
+
+use crate::config::ApiConfig;
+use crate::rate_limit::RateLimiter;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically removes the data which are not needed anymore
+pub async fn run(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+) {
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        rate_limiter.purge();
+
+        let pg_client = match pg_pool.get().await {
+            Ok(pg_client) => pg_client,
+            Err(err) => {
+                log::error!("Cleanup skipped (failed to get a client): {err}");
+                continue;
+            }
+        };
+
+        purge_deleted_players(&pg_client, &config).await;
+        purge_player_creations(&pg_client).await;
+        purge_player_creation_challenges(&pg_client).await;
+        purge_oidc_logins(&pg_client).await;
+        purge_game_refresh_tokens(&pg_client).await;
+    }
+}
+
+/// Evicts the registered game servers which stopped sending heartbeats
+pub async fn evict_game_servers(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+) {
+    let mut interval = tokio::time::interval(
+        config
+            .game_server_heartbeat_timeout
+            .max(Duration::from_secs(1)),
+    );
+    loop {
+        interval.tick().await;
+
+        let pg_client = match pg_pool.get().await {
+            Ok(pg_client) => pg_client,
+            Err(err) => {
+                log::error!("Game server eviction skipped (failed to get a client): {err}");
+                continue;
+            }
+        };
+
+        match pg_client
+            .prepare_typed_cached(
+                "DELETE FROM game_server_registrations WHERE last_heartbeat < NOW() - make_interval(secs => $1) RETURNING game_server_id",
+                &[Type::FLOAT8],
+            )
+            .await
+        {
+            Ok(statement) => {
+                match pg_client
+                    .query(
+                        &statement,
+                        &[&config.game_server_heartbeat_timeout.as_secs_f64()],
+                    )
+                    .await
+                {
+                    Ok(rows) => {
+                        for row in rows {
+                            let game_server_id: i32 = row.get(0);
+                            log::warn!("Game server {game_server_id} evicted (no heartbeat)");
+                        }
+                    }
+                    Err(err) => log::error!("Failed to evict game servers: {err}"),
+                }
+            }
+            Err(err) => {
+                log::error!("Failed to evict game servers (failed to prepare query): {err}");
+            }
+        }
+    }
+}
+
+async fn purge_deleted_players(pg_client: &deadpool_postgres::Client, config: &ApiConfig) {
+    // ships, permissions, tokens and others are removed by the cascading foreign keys
+    match pg_client
+        .prepare_typed_cached(
+            "DELETE FROM players WHERE deletion_time < NOW() - make_interval(secs => $1)",
+            &[Type::FLOAT8],
+        )
+        .await
+    {
+        Ok(statement) => {
+            match pg_client
+                .execute(
+                    &statement,
+                    &[&config.player_deletion_grace_period.as_secs_f64()],
+                )
+                .await
+            {
+                Ok(0) => {}
+                Ok(count) => log::info!("Deleted {count} players after their grace period"),
+                Err(err) => log::error!("Failed to delete players: {err}"),
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to delete players (failed to prepare query): {err}");
+        }
+    }
+}
+
+async fn purge_player_creations(pg_client: &deadpool_postgres::Client) {
+    // only the last day is needed to enforce the player creation limits
+    match pg_client
+        .prepare_typed_cached(
+            "DELETE FROM player_creations WHERE creation_time < NOW() - INTERVAL '1 day'",
+            &[],
+        )
+        .await
+    {
+        Ok(statement) => {
+            if let Err(err) = pg_client.execute(&statement, &[]).await {
+                log::error!("Failed to delete player creations: {err}");
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to delete player creations (failed to prepare query): {err}");
+        }
+    }
+}
+
+async fn purge_player_creation_challenges(pg_client: &deadpool_postgres::Client) {
+    // an expired challenge is refused anyway, no need to remember it was used
+    match pg_client
+        .prepare_typed_cached(
+            "DELETE FROM player_creation_challenges WHERE expire_timestamp < EXTRACT(EPOCH FROM NOW())",
+            &[],
+        )
+        .await
+    {
+        Ok(statement) => {
+            if let Err(err) = pg_client.execute(&statement, &[]).await {
+                log::error!("Failed to delete player creation challenges: {err}");
+            }
+        }
+        Err(err) => {
+            log::error!(
+                "Failed to delete player creation challenges (failed to prepare query): {err}"
+            );
+        }
+    }
+}
+
+async fn purge_oidc_logins(pg_client: &deadpool_postgres::Client) {
+    match pg_client
+        .prepare_typed_cached("DELETE FROM oidc_logins WHERE expires_at < NOW()", &[])
+        .await
+    {
+        Ok(statement) => {
+            if let Err(err) = pg_client.execute(&statement, &[]).await {
+                log::error!("Failed to delete OIDC logins: {err}");
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to delete OIDC logins (failed to prepare query): {err}");
+        }
+    }
+}
+
+async fn purge_game_refresh_tokens(pg_client: &deadpool_postgres::Client) {
+    // an expired refresh token is refused anyway, its family can't be used again
+    match pg_client
+        .prepare_typed_cached(
+            "DELETE FROM game_refresh_tokens WHERE expires_at < NOW()",
+            &[],
+        )
+        .await
+    {
+        Ok(statement) => {
+            if let Err(err) = pg_client.execute(&statement, &[]).await {
+                log::error!("Failed to delete game refresh tokens: {err}");
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to delete game refresh tokens (failed to prepare query): {err}");
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::http::header::HeaderMap;
+use ipnet::IpNet;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Finds the address of the client which sent the request
+///
+/// `X-Forwarded-For` is only read when the request comes from a trusted proxy, it is then walked
+/// from the right to skip the trusted proxies chain, the first untrusted address is the client.
+pub fn client_ip(
+    peer_addr: Option<SocketAddr>,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    let peer_ip = peer_addr?.ip();
+    if !is_trusted(&peer_ip, trusted_proxies) {
+        return Some(peer_ip);
+    }
+
+    let mut client_ip = peer_ip;
+    for ip in headers
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+    {
+        let Ok(ip) = ip.trim().parse::<IpAddr>() else {
+            // a malformed entry can't be trusted, stop at the last known address
+            break;
+        };
+
+        client_ip = ip;
+        if !is_trusted(&ip, trusted_proxies) {
+            break;
+        }
+    }
+
+    Some(client_ip)
+}
+
+/// Address used to count the requests of a client, IPv6 clients often get their own /56 prefix
+pub fn quota_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ipv6) => match ipv6.to_ipv4_mapped() {
+            Some(ipv4) => IpAddr::V4(ipv4),
+            None => {
+                let mut octets = ipv6.octets();
+                octets[7..16].fill(0);
+                IpAddr::V6(octets.into())
+            }
+        },
+    }
+}
+
+pub fn is_denied(ip: &IpAddr, denylist: &[IpNet]) -> bool {
+    denylist.iter().any(|net| net.contains(ip))
+}
+
+fn is_trusted(ip: &IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    trusted_proxies.iter().any(|net| net.contains(ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    use super::*;
+
+    fn headers(forwarded_for: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in forwarded_for {
+            headers.append(
+                HeaderName::from_static(X_FORWARDED_FOR),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    fn peer(ip: &str) -> Option<SocketAddr> {
+        Some(SocketAddr::new(ip.parse().unwrap(), 1234))
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_forwarded_for() {
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        assert_eq!(
+            client_ip(peer("203.0.113.7"), &headers(&["198.51.100.1"]), &trusted),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            client_ip(peer("10.0.0.1"), &headers(&["198.51.100.1"]), &[]),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn trusted_proxies_are_skipped_from_the_right() {
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        // the client can prepend any address, only the one appended by the proxy is used
+        assert_eq!(
+            client_ip(
+                peer("10.0.0.1"),
+                &headers(&["1.1.1.1, 198.51.100.1, 10.0.0.2"]),
+                &trusted
+            ),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            client_ip(
+                peer("10.0.0.1"),
+                &headers(&["1.1.1.1", "198.51.100.1"]),
+                &trusted
+            ),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn forwarded_for_stops_at_malformed_entries() {
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        assert_eq!(
+            client_ip(
+                peer("10.0.0.1"),
+                &headers(&["198.51.100.1, junk"]),
+                &trusted
+            ),
+            ip("10.0.0.1")
+        );
+        assert_eq!(
+            client_ip(peer("10.0.0.1"), &headers(&[]), &trusted),
+            ip("10.0.0.1")
+        );
+        assert_eq!(client_ip(None, &headers(&[]), &trusted), None);
+    }
+
+    #[test]
+    fn ipv6_quota_key_is_the_56_prefix() {
+        assert_eq!(
+            quota_key("2001:db8:aa:bb01:1:2:3:4".parse().unwrap()),
+            "2001:db8:aa:bb00::".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            quota_key("::ffff:198.51.100.1".parse().unwrap()),
+            "198.51.100.1".parse::<IpAddr>().unwrap()
+        );
+    }
+}
@@ -1,16 +1,18 @@
 use std::time::Duration;
 
+use ipnet::IpNet;
 use secure_string::SecureString;
 use serde::{Deserialize, Serialize};
-use serde_with::DurationSeconds;
 use serde_with::base64::Base64;
 use serde_with::serde_as;
+use serde_with::{DurationMilliSeconds, DurationSeconds};
 
 #[serde_as]
 #[derive(Serialize, Deserialize)]
 pub struct ApiConfig {
     pub listen_address: String,
     pub listen_port: u16,
+    pub trusted_proxies: Vec<IpNet>,
     pub repo_owner: String,
     pub game_repository: String,
     pub updater_repository: String,
@@ -18,24 +20,129 @@ pub struct ApiConfig {
     #[serde_as(as = "DurationSeconds<u64>")]
     pub cache_lifespan: Duration,
     pub github_pat: Option<SecureString>,
+    pub admin_api_key: Option<SecureString>,
     pub db_host: String,
     pub db_user: String,
     pub db_password: SecureString,
     pub db_database: String,
     pub player_nickname_maxlength: usize,
     pub player_allow_non_ascii: bool,
+    pub player_creation_hourly_limit: Option<u32>,
+    pub player_creation_daily_limit: Option<u32>,
+    pub player_creation_ip_denylist: Vec<IpNet>,
+    pub player_creation_challenge_enabled: bool,
+    pub player_creation_challenge_difficulty: u8,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub player_creation_challenge_duration: Duration,
+    pub player_creation_challenge_secret: SecureString,
+    pub player_nickname_blocklist: Option<String>,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub player_nickname_change_cooldown: Duration,
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub player_nickname_reservation: Option<Duration>,
+    pub player_token_pepper: SecureString,
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub player_token_duration: Option<Duration>,
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub player_token_idle_expiry: Option<Duration>,
+    pub player_recovery_code_count: usize,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub player_deletion_grace_period: Duration,
+    pub oidc_issuer: Option<String>,
+    pub oidc_client_id: String,
+    pub oidc_client_secret: SecureString,
+    pub oidc_redirect_uri: String,
+    pub oidc_scopes: String,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub oidc_login_duration: Duration,
     #[serde_as(as = "DurationSeconds<u64>")]
     pub game_api_access_token_duration: Duration,
     #[serde_as(as = "DurationSeconds<u64>")]
     pub game_api_refresh_token_duration: Duration,
-    pub game_api_secret: SecureString,
+    /// signs the game API tokens without `game_api_keys`, with them it only verifies the tokens it
+    /// signed before and can be removed `game_api_refresh_token_duration` after they were added
+    pub game_api_secret: Option<SecureString>,
     pub game_api_url: String,
     pub game_server_address: String,
     pub game_server_port: u16,
+    /// registered game servers are evicted when they stop sending heartbeats for this duration
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub game_server_heartbeat_timeout: Duration,
+    /// keys the hashes of the game server keys, distinct from `player_token_pepper`
+    pub game_server_key_pepper: SecureString,
+    pub game_dev_mode_enabled: bool,
+    pub game_dev_server_address: String,
     #[serde_as(as = "DurationSeconds<u64>")]
     pub connection_token_duration: Duration,
     #[serde_as(as = "Base64")]
     pub connection_token_key: [u8; 32],
+    /// players who stop polling their matchmaking ticket for this duration leave the queue
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub matchmaking_ticket_timeout: Duration,
+    /// longest wait of a matchmaking poll waiting for a match
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub matchmaking_long_poll_duration: Duration,
+    // arrays of tables must stay at the end of the file
+    pub rate_limits: Vec<RateLimitPolicy>,
+    pub matchmaking_modes: Vec<MatchmakingMode>,
+    /// optional, tokens are signed with `game_api_secret` if there are none
+    #[serde(default)]
+    pub game_api_keys: Vec<GameApiKeyConfig>,
+}
+
+/// Private key signing game API tokens, see `GameApiKeys`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameApiKeyConfig {
+    pub kid: String,
+    pub algorithm: GameApiKeyAlgorithm,
+    /// path of the PKCS#8 PEM file
+    pub private_key: String,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum GameApiKeyAlgorithm {
+    EdDSA,
+    ES256,
+}
+
+/// Limits the requests on the routes starting with `scope`, see `RateLimiter`
+#[serde_as]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RateLimitPolicy {
+    pub scope: String,
+    /// all methods if empty
+    #[serde(default)]
+    pub methods: Vec<String>,
+    pub key: RateLimitKey,
+    /// time needed to replenish one request
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub period: Duration,
+    pub burst: u32,
+}
+
+/// Players queued for the same mode and region are grouped in matches of `players`
+///
+/// A smaller match of at least `min_players` is formed once the first player of the queue waited
+/// for `max_wait`.
+#[serde_as]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MatchmakingMode {
+    pub name: String,
+    pub players: usize,
+    pub min_players: usize,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub max_wait: Duration,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// client IP, see `trusted_proxies`
+    Ip,
+    /// player of the game API token or admin key of the `Authorization` header,
+    /// the client IP is used if there are none or if they are invalid
+    Identity,
 }
 
 impl Default for ApiConfig {
@@ -43,26 +150,92 @@ impl Default for ApiConfig {
         Self {
             listen_address: "0.0.0.0".to_string(),
             listen_port: 14770,
+            trusted_proxies: Vec::new(),
             repo_owner: "DigitalpulseSoftware".to_string(),
             game_repository: "ThisSpaceOfMine".to_string(),
             updater_filename: "this_updater_of_mine".to_string(),
             updater_repository: "ThisUpdaterOfMine".to_string(),
             cache_lifespan: Duration::from_secs(5 * 60),
             github_pat: None,
+            admin_api_key: None,
             db_host: "localhost".to_string(),
             db_user: "api".to_string(),
             db_password: "password".into(),
             db_database: "tsom_db".to_string(),
             player_nickname_maxlength: 16,
             player_allow_non_ascii: false,
+            player_creation_hourly_limit: Some(3),
+            player_creation_daily_limit: Some(10),
+            player_creation_ip_denylist: Vec::new(),
+            player_creation_challenge_enabled: false,
+            player_creation_challenge_difficulty: 20,
+            player_creation_challenge_duration: Duration::from_secs(5 * 60),
+            player_creation_challenge_secret: "challenge".into(),
+            player_nickname_blocklist: None,
+            player_nickname_change_cooldown: Duration::from_secs(30 * 24 * 60 * 60),
+            player_nickname_reservation: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+            player_token_pepper: "pepper".into(),
+            player_token_duration: None,
+            player_token_idle_expiry: None,
+            player_recovery_code_count: 8,
+            player_deletion_grace_period: Duration::from_secs(30 * 24 * 60 * 60),
+            oidc_issuer: None,
+            oidc_client_id: "this_api_of_mine".to_string(),
+            oidc_client_secret: "secret".into(),
+            oidc_redirect_uri: "http://localhost:14771/oidc/callback".to_string(),
+            oidc_scopes: "openid profile".to_string(),
+            oidc_login_duration: Duration::from_secs(10 * 60),
             game_api_access_token_duration: Duration::from_secs(25 * 60),
             game_api_refresh_token_duration: Duration::from_secs(30 * 60),
-            game_api_secret: "secret".into(),
+            game_api_secret: Some("secret".into()),
             game_api_url: "http://localhost/game_server".to_string(),
             game_server_address: "localhost".to_string(),
             game_server_port: 29536,
+            game_server_heartbeat_timeout: Duration::from_secs(30),
+            game_server_key_pepper: "game server pepper".into(),
+            game_dev_mode_enabled: false,
+            game_dev_server_address: "localhost".to_string(),
             connection_token_duration: Duration::from_secs(5 * 60),
             connection_token_key: std::array::from_fn(|i| i as u8), // <=> [0, 1, .., 31]
+            matchmaking_ticket_timeout: Duration::from_secs(30),
+            matchmaking_long_poll_duration: Duration::from_secs(20),
+            rate_limits: vec![
+                RateLimitPolicy {
+                    scope: "/".to_string(),
+                    methods: Vec::new(),
+                    key: RateLimitKey::Ip,
+                    period: Duration::from_millis(500),
+                    burst: 8,
+                },
+                RateLimitPolicy {
+                    scope: "/v1/players".to_string(),
+                    methods: vec!["POST".to_string()],
+                    key: RateLimitKey::Ip,
+                    period: Duration::from_secs(10),
+                    burst: 1,
+                },
+                RateLimitPolicy {
+                    scope: "/v1/player/recover".to_string(),
+                    methods: Vec::new(),
+                    key: RateLimitKey::Ip,
+                    period: Duration::from_secs(60),
+                    burst: 3,
+                },
+                RateLimitPolicy {
+                    scope: "/game_server".to_string(),
+                    methods: Vec::new(),
+                    key: RateLimitKey::Identity,
+                    period: Duration::from_millis(100),
+                    burst: 20,
+                },
+            ],
+            matchmaking_modes: vec![MatchmakingMode {
+                name: "default".to_string(),
+                players: 8,
+                min_players: 2,
+                max_wait: Duration::from_secs(30),
+            }],
+            game_api_keys: Vec::new(),
         }
     }
 }
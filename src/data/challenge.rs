@@ -0,0 +1,188 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use rand_core::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+
+use crate::errors::Result;
+
+pub const MAX_DIFFICULTY: u8 = 32;
+
+const NONCE_SIZE: usize = 16;
+const SOLUTION_MAXLENGTH: usize = 64;
+
+/// Hashcash-style proof of work requested to create a player
+///
+/// The challenge is sent as `nonce.expire_timestamp.difficulty.signature`, the signature being
+/// an HMAC-SHA256 of the first three parts so the API doesn't have to store issued challenges.
+/// A solution is any string for which `SHA256("{challenge}:{solution}")` starts with at least
+/// `difficulty` zero bits.
+#[derive(Debug)]
+pub struct Challenge {
+    pub nonce: [u8; NONCE_SIZE],
+    pub expire_timestamp: u64,
+    pub difficulty: u8,
+}
+
+impl Challenge {
+    pub fn generate<R>(mut rng: R, duration: Duration, difficulty: u8) -> Result<Self>
+    where
+        R: CryptoRng + RngCore,
+    {
+        let mut nonce = [0u8; NONCE_SIZE];
+        rng.try_fill_bytes(&mut nonce)?;
+
+        let expire_timestamp = (SystemTime::now().duration_since(UNIX_EPOCH)? + duration).as_secs();
+
+        Ok(Self {
+            nonce,
+            expire_timestamp,
+            difficulty,
+        })
+    }
+
+    pub fn encode(&self, secret: &[u8]) -> String {
+        let payload = self.payload();
+        let signature = sign(secret, &payload);
+
+        format!("{payload}.{}", BASE64_URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    /// Decodes a challenge issued by [`Challenge::encode`], `None` if it was not signed by us
+    pub fn decode(secret: &[u8], challenge: &str) -> Option<Self> {
+        let (payload, signature) = challenge.rsplit_once('.')?;
+        let signature = BASE64_URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret).expect("HMAC can take a key of any size");
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+
+        let mut parts = payload.split('.');
+        let nonce = BASE64_URL_SAFE_NO_PAD
+            .decode(parts.next()?)
+            .ok()?
+            .try_into()
+            .ok()?;
+        let expire_timestamp = parts.next()?.parse().ok()?;
+        let difficulty = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Self {
+            nonce,
+            expire_timestamp,
+            difficulty,
+        })
+    }
+
+    pub fn is_expired(&self) -> Result<bool> {
+        Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() >= self.expire_timestamp)
+    }
+
+    pub fn is_solved_by(&self, challenge: &str, solution: &str) -> bool {
+        if solution.is_empty() || solution.len() > SOLUTION_MAXLENGTH {
+            return false;
+        }
+
+        let hash = Sha256::new()
+            .chain_update(challenge.as_bytes())
+            .chain_update(b":")
+            .chain_update(solution.as_bytes())
+            .finalize();
+
+        leading_zero_bits(&hash) >= u32::from(self.difficulty)
+    }
+
+    fn payload(&self) -> String {
+        format!(
+            "{}.{}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(self.nonce),
+            self.expire_timestamp,
+            self.difficulty
+        )
+    }
+}
+
+fn sign(secret: &[u8], payload: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC can take a key of any size");
+    mac.update(payload.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut count = 0;
+    for byte in bytes {
+        count += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use rand_core::OsRng;
+
+    use super::*;
+
+    #[test]
+    fn leading_zero_bits_counts_across_bytes() {
+        assert_eq!(leading_zero_bits(&[0xff, 0x00]), 0);
+        assert_eq!(leading_zero_bits(&[0x01, 0xff]), 7);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00, 0x10]), 19);
+        assert_eq!(leading_zero_bits(&[0x00, 0x80, 0x00]), 8);
+        assert_eq!(leading_zero_bits(&[0x00; 4]), 32);
+    }
+
+    #[test]
+    fn solution_must_reach_the_difficulty() {
+        let challenge = Challenge::generate(OsRng, Duration::from_secs(60), 8).unwrap();
+        let encoded = challenge.encode(b"secret");
+
+        let solution = (0u32..)
+            .map(|i| i.to_string())
+            .find(|solution| challenge.is_solved_by(&encoded, solution))
+            .unwrap();
+
+        let hash = Sha256::digest(format!("{encoded}:{solution}"));
+        assert_eq!(hash[0], 0);
+
+        let harder = Challenge {
+            difficulty: 24,
+            ..challenge
+        };
+        let hash_bits = leading_zero_bits(&hash);
+        assert_eq!(harder.is_solved_by(&encoded, &solution), hash_bits >= 24);
+    }
+
+    #[test]
+    fn empty_or_long_solutions_are_refused() {
+        let challenge = Challenge::generate(OsRng, Duration::from_secs(60), 0).unwrap();
+        let encoded = challenge.encode(b"secret");
+
+        assert!(challenge.is_solved_by(&encoded, "a"));
+        assert!(!challenge.is_solved_by(&encoded, ""));
+        assert!(!challenge.is_solved_by(&encoded, &"a".repeat(SOLUTION_MAXLENGTH + 1)));
+    }
+
+    #[test]
+    fn decode_checks_the_signature() {
+        let challenge = Challenge::generate(OsRng, Duration::from_secs(60), 12).unwrap();
+        let encoded = challenge.encode(b"secret");
+
+        let decoded = Challenge::decode(b"secret", &encoded).unwrap();
+        assert_eq!(decoded.nonce, challenge.nonce);
+        assert_eq!(decoded.expire_timestamp, challenge.expire_timestamp);
+        assert_eq!(decoded.difficulty, 12);
+
+        assert!(Challenge::decode(b"other secret", &encoded).is_none());
+
+        // lowering the difficulty invalidates the signature
+        let tampered = encoded.replacen(".12.", ".1.", 1);
+        assert!(Challenge::decode(b"secret", &tampered).is_none());
+    }
+}
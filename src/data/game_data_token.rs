@@ -1,6 +1,7 @@
 use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 fn default_as_false() -> bool {
@@ -13,7 +14,13 @@ pub struct GameDataToken {
     pub player_uuid: Uuid,
     #[serde(default = "default_as_false")]
     pub is_readonly: bool,
+    /// family of the refresh token, shared by every token obtained by refreshing it, unset on the
+    /// access tokens issued before they were bound to their family
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family_id: Option<Uuid>,
     // JWT fields
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<Uuid>, //< only set on refresh tokens
     pub exp: u64,
     pub iat: u64,
     pub sub: String, //< "access" or "refresh"
@@ -26,8 +33,12 @@ impl GameDataToken {
         player_uuid: Uuid,
         duration: Duration,
         is_readonly: bool,
+        family_id: Uuid,
     ) -> Self {
-        Self::new("access", player_db_id, player_uuid, duration, is_readonly)
+        Self {
+            family_id: Some(family_id),
+            ..Self::new("access", player_db_id, player_uuid, duration, is_readonly)
+        }
     }
 
     #[inline]
@@ -36,8 +47,24 @@ impl GameDataToken {
         player_uuid: Uuid,
         duration: Duration,
         is_readonly: bool,
+        family_id: Uuid,
     ) -> Self {
-        Self::new("refresh", player_db_id, player_uuid, duration, is_readonly)
+        Self {
+            family_id: Some(family_id),
+            jti: Some(Uuid::new_v4()),
+            ..Self::new("refresh", player_db_id, player_uuid, duration, is_readonly)
+        }
+    }
+
+    /// Stands for the jti of a refresh token issued before they had one, such a token is
+    /// identified by its claims as the same claims always give the same token
+    pub fn legacy_jti(&self) -> Uuid {
+        let digest = Sha256::digest(format!(
+            "{}:{}:{}:{}:{}:{}",
+            self.player_db_id, self.player_uuid, self.is_readonly, self.exp, self.iat, self.sub
+        ));
+
+        Uuid::from_slice(&digest[..16]).expect("a SHA-256 digest has more than 16 bytes")
     }
 
     fn new(
@@ -54,6 +81,8 @@ impl GameDataToken {
             player_db_id,
             player_uuid,
             is_readonly,
+            family_id: None,
+            jti: None,
             exp: now + duration.as_secs(),
             iat: now,
             sub: token_type.to_string(),
@@ -64,3 +93,33 @@ impl GameDataToken {
 fn valid_token_type(tt: &str) -> bool {
     tt == "access" || tt == "refresh"
 }
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::GameDataToken;
+
+    fn legacy_refresh_token(player_db_id: i32, player_uuid: Uuid, iat: u64) -> GameDataToken {
+        GameDataToken {
+            player_db_id,
+            player_uuid,
+            is_readonly: false,
+            family_id: None,
+            jti: None,
+            exp: iat + 1800,
+            iat,
+            sub: "refresh".to_string(),
+        }
+    }
+
+    #[test]
+    fn legacy_jti_identifies_the_claims() {
+        let uuid = Uuid::new_v4();
+        let jti = legacy_refresh_token(1, uuid, 1000).legacy_jti();
+
+        assert_eq!(jti, legacy_refresh_token(1, uuid, 1000).legacy_jti());
+        assert_ne!(jti, legacy_refresh_token(1, uuid, 1001).legacy_jti());
+        assert_ne!(jti, legacy_refresh_token(2, uuid, 1000).legacy_jti());
+    }
+}
@@ -1,4 +1,6 @@
+pub mod challenge;
 pub mod connection_token;
 pub mod game_data_token;
 pub mod player_data;
+pub mod recovery_code;
 pub mod token;
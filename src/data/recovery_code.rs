@@ -0,0 +1,69 @@
+use rand_core::{CryptoRng, RngCore};
+
+use crate::errors::Result;
+
+// Crockford base32, without the letters easily mistaken for digits
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const GROUP_SIZE: usize = 5;
+const GROUP_COUNT: usize = 2;
+
+/// One-time code allowing a player to get a new token, formatted as `XXXXX-XXXXX`
+pub fn generate<R>(mut rng: R) -> Result<String>
+where
+    R: CryptoRng + RngCore,
+{
+    let mut bytes = [0u8; GROUP_SIZE * GROUP_COUNT];
+    rng.try_fill_bytes(&mut bytes)?;
+
+    let code = bytes
+        .chunks(GROUP_SIZE)
+        .map(|group| {
+            group
+                .iter()
+                .map(|byte| ALPHABET[usize::from(byte & 31)] as char)
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("-");
+
+    Ok(code)
+}
+
+/// Normalizes a code typed by a player before hashing it
+///
+/// Case and separators are ignored and the letters confused with digits are read as such.
+pub fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand_core::OsRng;
+
+    use super::*;
+
+    #[test]
+    fn generated_codes_are_normalized() {
+        for _ in 0..100 {
+            let code = generate(OsRng).unwrap();
+            assert_eq!(code.len(), GROUP_SIZE * GROUP_COUNT + GROUP_COUNT - 1);
+            assert_eq!(code.as_bytes()[GROUP_SIZE], b'-');
+            assert_eq!(normalize(&code), code.replace('-', ""));
+        }
+    }
+
+    #[test]
+    fn normalize_ignores_case_separators_and_lookalikes() {
+        assert_eq!(normalize("ABCDE-FGH12"), "ABCDEFGH12");
+        assert_eq!(normalize(" abcde fgh12\n"), "ABCDEFGH12");
+        assert_eq!(normalize("oOiIl-L0123"), "0011110123");
+        assert_eq!(normalize("--"), "");
+    }
+}
@@ -3,8 +3,10 @@ use std::ops::Deref;
 
 use actix_web::web::BytesMut;
 use base64::prelude::*;
+use hmac::{Hmac, Mac};
 use rand_core::{CryptoRng, RngCore};
 use serde::Serialize;
+use sha2::Sha256;
 use tokio_postgres::types::{Format, IsNull, ToSql, Type};
 
 use crate::errors::Result;
@@ -23,6 +25,18 @@ impl Token {
 
         Ok(Self(BASE64_STANDARD.encode(key).into_boxed_str()))
     }
+
+    #[inline]
+    pub fn hash(&self, pepper: &[u8]) -> Vec<u8> {
+        hash_token(pepper, self)
+    }
+}
+
+/// Keyed hash (HMAC-SHA256) of a player token, only this hash is stored in the database
+pub fn hash_token(pepper: &[u8], token: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(pepper).expect("HMAC can take a key of any size");
+    mac.update(token.as_bytes());
+    mac.finalize().into_bytes().to_vec()
 }
 
 impl Deref for Token {
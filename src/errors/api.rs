@@ -1,5 +1,6 @@
 use actix_web::body::BoxBody;
 use actix_web::http::StatusCode;
+use actix_web::http::header::RETRY_AFTER;
 use actix_web::{HttpResponse, HttpResponseBuilder, ResponseError};
 use serde::Serialize;
 use std::fmt;
@@ -16,6 +17,8 @@ pub enum ErrorCause {
 pub struct RequestError {
     err_code: GeneralErrorCode,
     err_desc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    err_details: Option<serde_json::Value>,
 }
 
 #[derive(Debug)]
@@ -29,8 +32,14 @@ impl RequestError {
         Self {
             err_code: code,
             err_desc: description,
+            err_details: None,
         }
     }
+
+    pub fn with_details(mut self, details: Option<serde_json::Value>) -> Self {
+        self.err_details = details;
+        self
+    }
 }
 
 impl fmt::Display for RouteError {
@@ -65,8 +74,14 @@ impl ResponseError for RouteError {
                 if let Some(extra) = code.extra_info() {
                     log::error!("Extra info: {extra}");
                 }
+                if let Some(retry_after) = code.retry_after() {
+                    response.insert_header((RETRY_AFTER, retry_after));
+                }
 
-                response.json(RequestError::new(code.response_code(), description.clone()))
+                response.json(
+                    RequestError::new(code.response_code(), description.clone())
+                        .with_details(code.details()),
+                )
             }
         }
     }
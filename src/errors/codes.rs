@@ -5,6 +5,11 @@ use serde::{Serialize, Serializer};
 
 use super::InternalError;
 
+/// End of the token shown in the logs and error messages, never the full token
+pub fn token_suffix(token: &str) -> &str {
+    token.get(token.len().saturating_sub(6)..).unwrap_or("")
+}
+
 #[derive(Debug)]
 pub enum GeneralErrorCode {
     FetchLatestRelease,
@@ -13,10 +18,49 @@ pub enum GeneralErrorCode {
     NicknameEmpty,
     NicknameToolong,
     NicknameForbiddenCharacters,
+    NicknameMixedScripts,
+    NicknameForbidden,
+    NicknameTaken,
+    NicknameChangeCooldown,
+    NicknameReserved,
+
+    PlayerPendingDeletion,
+    PlayerNotPendingDeletion,
+    PlayerBanned,
+    PlayerCreationLimitReached,
+    IpDenied,
+    RateLimited,
+
+    ChallengeDisabled,
+    ChallengeRequired,
+    InvalidChallenge,
+    InvalidChallengeDifficulty,
+
+    OidcDisabled,
+    InvalidOidcState,
+    OidcAuthenticationFailed,
+    IdentityAlreadyLinked,
 
     AuthenticationInvalidToken,
+    ExpiredToken,
     InvalidToken,
     InvalidId,
+    InvalidDeviceName,
+    InvalidRecoveryCode,
+    InvalidPermission,
+    InvalidRole,
+    InvalidBanDuration,
+
+    InvalidAdminCredentials,
+    InvalidGameServerCredentials,
+    InvalidGameServerName,
+    InvalidGameServerRegistration,
+    GameServerNotRegistered,
+    NoServerAvailable,
+    UnknownMatchmakingMode,
+    InvalidMatchmakingTicket,
+
+    DevModeForbidden,
 
     // error due to an error in the server
     Internal,
@@ -34,11 +78,51 @@ pub enum ServerErrorCode {
     NicknameEmpty,
     NicknameToolong,
     NicknameForbiddenCharacters,
+    NicknameMixedScripts,
+    NicknameForbidden(String),
+    NicknameTaken,
+    NicknameChangeCooldown(i64),
+    NicknameReserved,
+
+    PlayerPendingDeletion,
+    PlayerNotPendingDeletion,
+    PlayerBanned {
+        reason: String,
+        expire_timestamp: Option<i64>,
+    },
+    PlayerCreationLimitReached(u64),
+    IpDenied(std::net::IpAddr),
+    RateLimited(u64),
+
+    ChallengeDisabled,
+    ChallengeRequired,
+    InvalidChallenge,
+    InvalidChallengeDifficulty,
+
+    OidcDisabled,
+    InvalidOidcState,
+    OidcAuthenticationFailed,
+    IdentityAlreadyLinked,
 
     AuthenticationInvalidToken(String),
+    ExpiredToken(String),
     EmptyToken,
     InvalidToken(Option<String>),
     InvalidId,
+    InvalidDeviceName,
+    InvalidRecoveryCode,
+    InvalidPermission,
+    InvalidRole,
+    InvalidBanDuration,
+    InvalidAdminCredentials,
+    InvalidGameServerCredentials,
+    InvalidGameServerName,
+    InvalidGameServerRegistration,
+    GameServerNotRegistered,
+    NoServerAvailable,
+    UnknownMatchmakingMode,
+    InvalidMatchmakingTicket,
+    DevModeForbidden,
     TokenGenerationFailed,
     JWTAccident(jsonwebtoken::errors::Error),
 
@@ -57,10 +141,49 @@ impl GeneralErrorCode {
             Self::NicknameEmpty => "nickname_empty",
             Self::NicknameToolong => "nickname_toolong",
             Self::NicknameForbiddenCharacters => "nickname_forbidden_characters",
+            Self::NicknameMixedScripts => "nickname_mixed_scripts",
+            Self::NicknameForbidden => "nickname_forbidden",
+            Self::NicknameTaken => "nickname_taken",
+            Self::NicknameChangeCooldown => "nickname_change_cooldown",
+            Self::NicknameReserved => "nickname_reserved",
+
+            Self::PlayerPendingDeletion => "player_pending_deletion",
+            Self::PlayerNotPendingDeletion => "player_not_pending_deletion",
+            Self::PlayerBanned => "player_banned",
+            Self::PlayerCreationLimitReached => "player_creation_limit_reached",
+            Self::IpDenied => "ip_denied",
+            Self::RateLimited => "rate_limited",
+
+            Self::ChallengeDisabled => "challenge_disabled",
+            Self::ChallengeRequired => "challenge_required",
+            Self::InvalidChallenge => "invalid_challenge",
+            Self::InvalidChallengeDifficulty => "invalid_challenge_difficulty",
+
+            Self::OidcDisabled => "oidc_disabled",
+            Self::InvalidOidcState => "invalid_oidc_state",
+            Self::OidcAuthenticationFailed => "oidc_authentication_failed",
+            Self::IdentityAlreadyLinked => "identity_already_linked",
 
             Self::AuthenticationInvalidToken => "authentication_invalid_token",
+            Self::ExpiredToken => "expired_token",
             Self::InvalidToken => "invalid_token",
             Self::InvalidId => "invalid_id",
+            Self::InvalidDeviceName => "invalid_device_name",
+            Self::InvalidRecoveryCode => "invalid_recovery_code",
+            Self::InvalidPermission => "invalid_permission",
+            Self::InvalidRole => "invalid_role",
+            Self::InvalidBanDuration => "invalid_ban_duration",
+
+            Self::InvalidAdminCredentials => "invalid_admin_credentials",
+            Self::InvalidGameServerCredentials => "invalid_game_server_credentials",
+            Self::InvalidGameServerName => "invalid_game_server_name",
+            Self::InvalidGameServerRegistration => "invalid_game_server_registration",
+            Self::GameServerNotRegistered => "game_server_not_registered",
+            Self::NoServerAvailable => "no_server_available",
+            Self::UnknownMatchmakingMode => "unknown_matchmaking_mode",
+            Self::InvalidMatchmakingTicket => "invalid_matchmaking_ticket",
+
+            Self::DevModeForbidden => "dev_mode_forbidden",
 
             Self::Internal => "api_internal",
         }
@@ -78,9 +201,68 @@ impl GeneralErrorCode {
             Self::NicknameForbiddenCharacters => {
                 "The given nickname has an invalid character inside, please change it"
             }
+            Self::NicknameMixedScripts => {
+                "The given nickname mixes characters from different scripts, please change it"
+            }
+            Self::NicknameForbidden => "The given nickname is not allowed, please change it",
+            Self::NicknameTaken => "The given nickname is already taken, please change it",
+            Self::NicknameChangeCooldown => {
+                "The nickname has been changed too recently, please retry later"
+            }
+            Self::NicknameReserved => {
+                "The given nickname has recently been used by another player, please change it"
+            }
+            Self::PlayerPendingDeletion => {
+                "The account is pending deletion, cancel the deletion to use it again"
+            }
+            Self::PlayerNotPendingDeletion => "The account is not pending deletion",
+            Self::PlayerBanned => "The account is banned",
+            Self::PlayerCreationLimitReached => {
+                "Too many players have been created from this address, please retry later"
+            }
+            Self::IpDenied => "Requests from this address are not allowed",
+            Self::RateLimited => "Too many requests, please retry later",
+
+            Self::ChallengeDisabled => "Player creation doesn't require a challenge",
+            Self::ChallengeRequired => "Player creation requires a solved challenge",
+            Self::InvalidChallenge => {
+                "The given challenge is invalid, expired, already used or not solved"
+            }
+            Self::InvalidChallengeDifficulty => "The given challenge difficulty is too high",
+
+            Self::OidcDisabled => "Login with an external account is disabled",
+            Self::InvalidOidcState => "The given login state is invalid, expired or already used",
+            Self::OidcAuthenticationFailed => {
+                "The external account could not be authenticated, please retry"
+            }
+            Self::IdentityAlreadyLinked => {
+                "The external account is already linked to another player"
+            }
+
             Self::AuthenticationInvalidToken => "The given authentication token is invalid",
+            Self::ExpiredToken => "The given authentication token has expired",
             Self::InvalidToken => "The given token is invalid",
             Self::InvalidId => "The given id has never been attributed to anyone",
+            Self::InvalidDeviceName => "The given device name is empty or too long",
+            Self::InvalidRecoveryCode => "The given recovery code is invalid or already used",
+            Self::InvalidPermission => "The given permission is invalid",
+            Self::InvalidRole => "The given role is invalid",
+            Self::InvalidBanDuration => "The given ban duration is too long",
+
+            Self::InvalidAdminCredentials => "The given admin credentials are invalid",
+            Self::InvalidGameServerCredentials => "The given game server credentials are invalid",
+            Self::InvalidGameServerName => "The given game server name is invalid",
+            Self::InvalidGameServerRegistration => "The game server registration is invalid",
+            Self::GameServerNotRegistered => {
+                "The game server isn't registered or has been evicted, please register again"
+            }
+            Self::NoServerAvailable => "No game server is available, please retry later",
+            Self::UnknownMatchmakingMode => "The given matchmaking mode doesn't exist",
+            Self::InvalidMatchmakingTicket => {
+                "The matchmaking ticket is unknown or has expired, please queue again"
+            }
+
+            Self::DevModeForbidden => "Dev mode is disabled or not allowed for this player",
 
             Self::Internal => "An internal error occured on the server, please retry later",
         }
@@ -88,7 +270,21 @@ impl GeneralErrorCode {
 
     pub fn status_code(&self) -> StatusCode {
         match self {
-            Self::FetchLatestRelease | Self::NotFoundPlatform => StatusCode::NOT_FOUND,
+            Self::FetchLatestRelease | Self::NotFoundPlatform | Self::GameServerNotRegistered => {
+                StatusCode::NOT_FOUND
+            }
+            Self::NicknameTaken | Self::IdentityAlreadyLinked => StatusCode::CONFLICT,
+            Self::PlayerPendingDeletion | Self::PlayerBanned | Self::IpDenied => {
+                StatusCode::FORBIDDEN
+            }
+            Self::InvalidAdminCredentials | Self::InvalidGameServerCredentials => {
+                StatusCode::UNAUTHORIZED
+            }
+            Self::DevModeForbidden => StatusCode::FORBIDDEN,
+            Self::NicknameChangeCooldown | Self::PlayerCreationLimitReached | Self::RateLimited => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::NoServerAvailable => StatusCode::SERVICE_UNAVAILABLE,
             Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
             _ => StatusCode::BAD_REQUEST,
         }
@@ -113,10 +309,47 @@ impl ServerErrorCode {
             Self::NicknameEmpty => GeneralErrorCode::NicknameEmpty,
             Self::NicknameToolong => GeneralErrorCode::NicknameToolong,
             Self::NicknameForbiddenCharacters => GeneralErrorCode::NicknameForbiddenCharacters,
+            Self::NicknameMixedScripts => GeneralErrorCode::NicknameMixedScripts,
+            Self::NicknameForbidden(_) => GeneralErrorCode::NicknameForbidden,
+            Self::NicknameTaken => GeneralErrorCode::NicknameTaken,
+            Self::NicknameChangeCooldown(_) => GeneralErrorCode::NicknameChangeCooldown,
+            Self::NicknameReserved => GeneralErrorCode::NicknameReserved,
+
+            Self::PlayerPendingDeletion => GeneralErrorCode::PlayerPendingDeletion,
+            Self::PlayerNotPendingDeletion => GeneralErrorCode::PlayerNotPendingDeletion,
+            Self::PlayerBanned { .. } => GeneralErrorCode::PlayerBanned,
+            Self::PlayerCreationLimitReached(_) => GeneralErrorCode::PlayerCreationLimitReached,
+            Self::IpDenied(_) => GeneralErrorCode::IpDenied,
+            Self::RateLimited(_) => GeneralErrorCode::RateLimited,
+
+            Self::ChallengeDisabled => GeneralErrorCode::ChallengeDisabled,
+            Self::ChallengeRequired => GeneralErrorCode::ChallengeRequired,
+            Self::InvalidChallenge => GeneralErrorCode::InvalidChallenge,
+            Self::InvalidChallengeDifficulty => GeneralErrorCode::InvalidChallengeDifficulty,
+
+            Self::OidcDisabled => GeneralErrorCode::OidcDisabled,
+            Self::InvalidOidcState => GeneralErrorCode::InvalidOidcState,
+            Self::OidcAuthenticationFailed => GeneralErrorCode::OidcAuthenticationFailed,
+            Self::IdentityAlreadyLinked => GeneralErrorCode::IdentityAlreadyLinked,
 
             Self::AuthenticationInvalidToken(_) => GeneralErrorCode::AuthenticationInvalidToken,
+            Self::ExpiredToken(_) => GeneralErrorCode::ExpiredToken,
             Self::EmptyToken | Self::InvalidToken(_) => GeneralErrorCode::InvalidToken,
             Self::InvalidId => GeneralErrorCode::InvalidId,
+            Self::InvalidDeviceName => GeneralErrorCode::InvalidDeviceName,
+            Self::InvalidRecoveryCode => GeneralErrorCode::InvalidRecoveryCode,
+            Self::InvalidPermission => GeneralErrorCode::InvalidPermission,
+            Self::InvalidRole => GeneralErrorCode::InvalidRole,
+            Self::InvalidBanDuration => GeneralErrorCode::InvalidBanDuration,
+            Self::InvalidAdminCredentials => GeneralErrorCode::InvalidAdminCredentials,
+            Self::InvalidGameServerCredentials => GeneralErrorCode::InvalidGameServerCredentials,
+            Self::InvalidGameServerName => GeneralErrorCode::InvalidGameServerName,
+            Self::InvalidGameServerRegistration => GeneralErrorCode::InvalidGameServerRegistration,
+            Self::GameServerNotRegistered => GeneralErrorCode::GameServerNotRegistered,
+            Self::NoServerAvailable => GeneralErrorCode::NoServerAvailable,
+            Self::UnknownMatchmakingMode => GeneralErrorCode::UnknownMatchmakingMode,
+            Self::InvalidMatchmakingTicket => GeneralErrorCode::InvalidMatchmakingTicket,
+            Self::DevModeForbidden => GeneralErrorCode::DevModeForbidden,
 
             Self::TokenGenerationFailed
             | Self::JWTAccident(_)
@@ -125,7 +358,7 @@ impl ServerErrorCode {
         }
     }
 
-    pub fn extra_info(&self) -> Option<Cow<str>> {
+    pub fn extra_info(&self) -> Option<Cow<'_, str>> {
         match self {
             Self::InvalidSha256(parts, assets) => Some(Cow::Owned(format!(
                 "The SHA256 file of {assets} has {parts} parts, please fix it!"
@@ -136,22 +369,52 @@ impl ServerErrorCode {
             Self::NotFoundPlatform(platform) => Some(Cow::Owned(format!(
                 "Someone is trying to play with the {platform} platform"
             ))),
+            Self::NicknameForbidden(entry) => Some(Cow::Owned(format!(
+                "The nickname matched the blocklist entry {entry}"
+            ))),
+            Self::IpDenied(ip) => Some(Cow::Owned(format!("The address {ip} is denied"))),
             Self::JWTAccident(error) => Some(Cow::Owned(error.to_string())),
             Self::AuthenticationInvalidToken(token) => Some(Cow::Owned(format!(
                 "The authentication token '...{}' is invalid",
-                &token[token.len() - 6..token.len()]
+                token_suffix(token)
+            ))),
+            Self::ExpiredToken(token) => Some(Cow::Owned(format!(
+                "The authentication token '...{}' has expired",
+                token_suffix(token)
             ))),
             Self::InvalidToken(extra) => extra.as_deref().map(|token| {
-                Cow::Owned(format!(
-                    "The token '...{}' is invalid",
-                    &token[token.len() - 6..token.len()]
-                ))
+                Cow::Owned(format!("The token '...{}' is invalid", token_suffix(token)))
             }),
             Self::External(info) => Some(Cow::Borrowed(info)),
 
             _ => None,
         }
     }
+
+    /// Seconds to wait before retrying, sent in the `Retry-After` header
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            Self::NicknameChangeCooldown(remaining) => Some((*remaining).max(0) as u64),
+            Self::PlayerCreationLimitReached(wait) | Self::RateLimited(wait) => Some(*wait),
+
+            _ => None,
+        }
+    }
+
+    /// Additional data sent to the client along the error
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::PlayerBanned {
+                reason,
+                expire_timestamp,
+            } => Some(serde_json::json!({
+                "reason": reason,
+                "expire_timestamp": expire_timestamp,
+            })),
+
+            _ => None,
+        }
+    }
 }
 
 impl From<InternalError> for ServerErrorCode {
@@ -170,3 +433,37 @@ impl From<InternalError> for ServerErrorCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ServerErrorCode, token_suffix};
+
+    #[test]
+    fn token_suffix_keeps_the_last_bytes() {
+        assert_eq!(token_suffix("0123456789abcdef"), "abcdef");
+        assert_eq!(token_suffix("abcdef"), "abcdef");
+    }
+
+    #[test]
+    fn token_suffix_handles_short_and_non_ascii_tokens() {
+        assert_eq!(token_suffix(""), "");
+        assert_eq!(token_suffix("abc"), "abc");
+        // the 6th byte from the end is inside a multi-byte character
+        assert_eq!(token_suffix("€a€"), "");
+        assert_eq!(token_suffix("ééééé"), "ééé");
+    }
+
+    #[test]
+    fn limits_tell_when_to_retry() {
+        assert_eq!(
+            ServerErrorCode::PlayerCreationLimitReached(120).retry_after(),
+            Some(120)
+        );
+        assert_eq!(ServerErrorCode::RateLimited(3).retry_after(), Some(3));
+        assert_eq!(
+            ServerErrorCode::NicknameChangeCooldown(-5).retry_after(),
+            Some(0)
+        );
+        assert_eq!(ServerErrorCode::NicknameTaken.retry_after(), None);
+    }
+}
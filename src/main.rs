@@ -1,6 +1,6 @@
 use std::borrow::Cow;
+use std::sync::atomic::AtomicU8;
 
-use actix_governor::{Governor, GovernorConfig, GovernorConfigBuilder};
 use actix_web::{App, HttpServer, middleware, web};
 use cached::TimedCache;
 use confy::ConfyError;
@@ -11,15 +11,27 @@ use crate::app_data::AppData;
 use crate::config::ApiConfig;
 use crate::errors::Result;
 use crate::fetcher::Fetcher;
+use crate::game_api_keys::GameApiKeys;
+use crate::matchmaking::Matchmaker;
+use crate::nickname::blocklist::NicknameBlocklist;
+use crate::oidc::OidcClient;
+use crate::rate_limit::RateLimiter;
 
 mod app_data;
+mod cleanup;
+mod client_ip;
 mod config;
 mod data;
 mod deku_helper;
 mod errors;
 mod fetcher;
+mod game_api_keys;
 mod game_data;
+mod matchmaking;
 mod metaprog;
+mod nickname;
+mod oidc;
+mod rate_limit;
 mod routes;
 
 const CONFIG_FILE: Cow<'static, str> = Cow::Borrowed("tsom_api_config.toml");
@@ -51,7 +63,9 @@ async fn main() -> Result<(), std::io::Error> {
     }
     env_logger::init();
 
-    rustls::crypto::ring::default_provider().install_default().expect("Failed to install rustls crypto provider");
+    rustls::crypto::ring::default_provider()
+        .install_default()
+        .expect("Failed to install rustls crypto provider");
 
     let mut args = std::env::args();
     args.next(); // skip the executable name
@@ -74,6 +88,20 @@ async fn main() -> Result<(), std::io::Error> {
         }
     };
     let fetcher = Fetcher::from_config(&config).unwrap();
+    let nickname_blocklist = NicknameBlocklist::from_config(&config).unwrap();
+    let oidc = OidcClient::from_config(&config);
+    let game_api_keys = match GameApiKeys::from_config(&config) {
+        Ok(game_api_keys) => game_api_keys,
+        Err(err) => panic!("failed to load the game API keys: {err:?}"),
+    };
+    let rate_limiter = match RateLimiter::from_config(&config) {
+        Ok(rate_limiter) => web::Data::new(rate_limiter),
+        Err(err) => panic!("wrong rate limits in the file {config_file}: {err}"),
+    };
+    let matchmaker = match Matchmaker::from_config(&config) {
+        Ok(matchmaker) => web::Data::new(matchmaker),
+        Err(err) => panic!("wrong matchmaking modes in the file {config_file}: {err}"),
+    };
 
     log::info!("Connection to the database");
     let pg_pool = match setup_pg_pool(&config).await {
@@ -91,41 +119,97 @@ async fn main() -> Result<(), std::io::Error> {
         }
     };
 
+    // the nicknames must all have a key before players can pick new nicknames
+    match pg_pool.get().await {
+        Ok(pg_client) => {
+            if let Err(err) = nickname::backfill_keys(&pg_client).await {
+                panic!("failed to backfill the nickname keys: {err}")
+            }
+        }
+        Err(err) => panic!("failed to connect to database: {err}"),
+    }
+
     let bind_address = format!("{}:{}", config.listen_address, config.listen_port);
 
     let data_config = web::Data::new(AppData {
         cache: Mutex::new(TimedCache::with_lifespan(config.cache_lifespan)), // 5min
         fetcher,
+        nickname_blocklist,
+        challenge_difficulty: AtomicU8::new(config.player_creation_challenge_difficulty),
+        oidc,
+        game_api_keys,
     });
     let config = web::Data::new(config);
 
-    let governor_conf = GovernorConfig::default();
-
-    let player_create_governor_conf = GovernorConfigBuilder::default()
-        .seconds_per_request(10)
-        .burst_size(1)
-        .finish()
-        .unwrap();
+    tokio::spawn(cleanup::run(
+        pg_pool.clone(),
+        config.clone(),
+        rate_limiter.clone(),
+    ));
+    tokio::spawn(nickname::blocklist::watch(data_config.clone()));
+    tokio::spawn(cleanup::evict_game_servers(pg_pool.clone(), config.clone()));
+    tokio::spawn(matchmaking::run(
+        matchmaker.clone(),
+        pg_pool.clone(),
+        config.clone(),
+    ));
 
     log::info!("Server starting at the address {bind_address}");
     HttpServer::new(move || {
         App::new()
             .wrap(middleware::Logger::default())
-            .wrap(Governor::new(&governor_conf))
+            .wrap(middleware::from_fn(rate_limit::rate_limit))
             .app_data(data_config.clone())
             .app_data(config.clone())
             .app_data(pg_pool.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(matchmaker.clone())
             .service(routes::version::game_version)
+            .service(routes::players::create)
             .service(routes::players::auth)
+            .service(routes::players::rename)
+            .service(routes::players::nickname_available)
+            .service(routes::players::creation_challenge)
+            .service(routes::account::delete)
+            .service(routes::account::cancel_deletion)
+            .service(routes::account::export)
+            .service(routes::oidc::authorize)
+            .service(routes::oidc::finish_login)
+            .service(routes::recovery::recover)
+            .service(routes::recovery::regenerate_recovery_codes)
+            .service(routes::tokens::create_token)
+            .service(routes::tokens::list_tokens)
+            .service(routes::tokens::revoke_token)
+            .service(routes::tokens::rotate_token)
+            .service(routes::tokens::revoke_tokens)
             .service(routes::connection::game_connect)
+            .service(routes::matchmaking::enqueue)
+            .service(routes::matchmaking::poll)
+            .service(routes::matchmaking::cancel)
+            .service(routes::admin::player_permissions_get)
+            .service(routes::admin::player_permission_grant)
+            .service(routes::admin::player_permission_revoke)
+            .service(routes::admin::player_role_assign)
+            .service(routes::admin::player_role_unassign)
+            .service(routes::admin::player_bans_get)
+            .service(routes::admin::player_ban)
+            .service(routes::admin::player_unban)
+            .service(routes::admin::roles_get)
+            .service(routes::admin::challenge_get)
+            .service(routes::admin::challenge_update)
+            .service(routes::admin::role_permission_grant)
+            .service(routes::admin::role_permission_revoke)
+            .service(routes::admin::game_servers_get)
+            .service(routes::admin::game_server_create)
+            .service(routes::admin::game_server_delete)
+            .service(routes::game_server::jwks)
             .service(routes::game_server::refresh_access_token)
+            .service(routes::game_server::introspect)
+            .service(routes::registry::register)
+            .service(routes::registry::heartbeat)
+            .service(routes::registry::unregister)
             .service(routes::game_server::player_ship_get)
             .service(routes::game_server::player_ship_patch)
-            .service(
-                web::scope("")
-                    .wrap(Governor::new(&player_create_governor_conf))
-                    .service(routes::players::create),
-            )
     })
     .bind(bind_address)?
     .run()
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::web;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::config::{ApiConfig, MatchmakingMode};
+use crate::routes::registry::{SelectedGameServer, select_game_server};
+
+const MATCHMAKING_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub enum TicketState {
+    Queued,
+    /// the player can get its connection token to the game server of the match
+    Matched(Arc<SelectedGameServer>),
+}
+
+struct Ticket {
+    player_id: i32,
+    mode: String,
+    region: Option<String>,
+    enqueue_time: Instant,
+    last_poll: Instant,
+    /// set while a game server is searched for the match of this ticket
+    in_match: bool,
+    state: watch::Sender<TicketState>,
+}
+
+struct PendingMatch {
+    mode: String,
+    region: Option<String>,
+    tickets: Vec<Uuid>,
+}
+
+/// Queue of the players waiting for a match, kept in memory
+///
+/// Matches are formed by [`run`] according to `matchmaking_modes`, each match is sent to a game
+/// server chosen like `/v1/game/connect` does (see `select_game_server`).
+pub struct Matchmaker {
+    modes: HashMap<String, MatchmakingMode>,
+    tickets: Mutex<HashMap<Uuid, Ticket>>,
+}
+
+impl Matchmaker {
+    pub fn from_config(config: &ApiConfig) -> Result<Self, String> {
+        let mut modes = HashMap::new();
+        for mode in &config.matchmaking_modes {
+            if mode.players == 0 || mode.min_players == 0 || mode.min_players > mode.players {
+                return Err(format!(
+                    "matchmaking mode {} must have 1 <= min_players <= players",
+                    mode.name
+                ));
+            }
+
+            if modes.insert(mode.name.clone(), mode.clone()).is_some() {
+                return Err(format!("matchmaking mode {} is defined twice", mode.name));
+            }
+        }
+
+        Ok(Self {
+            modes,
+            tickets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn has_mode(&self, mode: &str) -> bool {
+        self.modes.contains_key(mode)
+    }
+
+    /// Queues the player, its previous ticket is replaced
+    pub fn enqueue(&self, player_id: i32, mode: &str, region: Option<&str>) -> Uuid {
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.retain(|_, ticket| ticket.player_id != player_id);
+
+        let ticket_id = Uuid::new_v4();
+        let now = Instant::now();
+        tickets.insert(
+            ticket_id,
+            Ticket {
+                player_id,
+                mode: mode.to_string(),
+                region: region.map(str::to_string),
+                enqueue_time: now,
+                last_poll: now,
+                in_match: false,
+                state: watch::Sender::new(TicketState::Queued),
+            },
+        );
+
+        ticket_id
+    }
+
+    /// Keeps the ticket of the player in the queue and returns its state
+    pub fn poll(&self, ticket_id: Uuid, player_id: i32) -> Option<watch::Receiver<TicketState>> {
+        let mut tickets = self.tickets.lock().unwrap();
+        let ticket = tickets
+            .get_mut(&ticket_id)
+            .filter(|ticket| ticket.player_id == player_id)?;
+
+        ticket.last_poll = Instant::now();
+        Some(ticket.state.subscribe())
+    }
+
+    /// Removes the ticket of the player, once it left the queue or got its connection token
+    pub fn remove(&self, ticket_id: Uuid, player_id: i32) -> bool {
+        let mut tickets = self.tickets.lock().unwrap();
+        if tickets
+            .get(&ticket_id)
+            .is_some_and(|ticket| ticket.player_id == player_id)
+        {
+            tickets.remove(&ticket_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Groups the queued players in matches and sends each match to a game server
+    ///
+    /// Players of a match for which no game server is available stay in the queue.
+    pub async fn form_matches(&self, pg_client: &deadpool_postgres::Client, config: &ApiConfig) {
+        for pending_match in self.take_pending_matches(config.matchmaking_ticket_timeout) {
+            let game_server = select_game_server(
+                pg_client,
+                config,
+                pending_match.region.as_deref(),
+                None,
+                pending_match.tickets.len() as i32,
+            )
+            .await;
+
+            let mut tickets = self.tickets.lock().unwrap();
+            let game_server = match game_server {
+                Ok(game_server) => Some(Arc::new(game_server)),
+                Err(err) => {
+                    log::warn!(
+                        "Match of {} players ({}, region {:?}) postponed: {err}",
+                        pending_match.tickets.len(),
+                        pending_match.mode,
+                        pending_match.region
+                    );
+                    None
+                }
+            };
+
+            for ticket_id in &pending_match.tickets {
+                // the player may have left the queue in the meantime
+                let Some(ticket) = tickets.get_mut(ticket_id) else {
+                    continue;
+                };
+
+                ticket.in_match = false;
+                if let Some(game_server) = &game_server {
+                    ticket
+                        .state
+                        .send_replace(TicketState::Matched(game_server.clone()));
+                }
+            }
+
+            if let Some(game_server) = &game_server {
+                log::info!(
+                    "Match of {} players ({}) sent to game server {:?}",
+                    pending_match.tickets.len(),
+                    pending_match.mode,
+                    game_server.game_server_id
+                );
+            }
+        }
+    }
+
+    fn take_pending_matches(&self, ticket_timeout: Duration) -> Vec<PendingMatch> {
+        let mut tickets = self.tickets.lock().unwrap();
+
+        tickets.retain(|_, ticket| ticket.in_match || ticket.last_poll.elapsed() < ticket_timeout);
+
+        let mut queues = HashMap::<_, Vec<(Uuid, Instant)>>::new();
+        for (ticket_id, ticket) in tickets.iter() {
+            if ticket.in_match || matches!(*ticket.state.borrow(), TicketState::Matched(_)) {
+                continue;
+            }
+
+            queues
+                .entry((ticket.mode.as_str(), ticket.region.as_deref()))
+                .or_default()
+                .push((*ticket_id, ticket.enqueue_time));
+        }
+
+        let mut pending_matches = Vec::new();
+        for ((mode_name, region), mut queue) in queues {
+            let Some(mode) = self.modes.get(mode_name) else {
+                continue;
+            };
+
+            queue.sort_by_key(|(_, enqueue_time)| *enqueue_time);
+
+            let mut queue = queue.as_slice();
+            while !queue.is_empty() {
+                let oldest_wait = queue[0].1.elapsed();
+                let player_count = if queue.len() >= mode.players {
+                    mode.players
+                } else if queue.len() >= mode.min_players && oldest_wait >= mode.max_wait {
+                    queue.len()
+                } else {
+                    break;
+                };
+
+                let (match_queue, remaining) = queue.split_at(player_count);
+                pending_matches.push(PendingMatch {
+                    mode: mode_name.to_string(),
+                    region: region.map(str::to_string),
+                    tickets: match_queue
+                        .iter()
+                        .map(|(ticket_id, _)| *ticket_id)
+                        .collect(),
+                });
+                queue = remaining;
+            }
+        }
+
+        for ticket_id in pending_matches
+            .iter()
+            .flat_map(|pending_match| &pending_match.tickets)
+        {
+            if let Some(ticket) = tickets.get_mut(ticket_id) {
+                ticket.in_match = true;
+            }
+        }
+
+        pending_matches
+    }
+}
+
+/// Periodically forms the matches, including the smaller ones waiting for `max_wait`
+pub async fn run(
+    matchmaker: web::Data<Matchmaker>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+) {
+    let mut interval = tokio::time::interval(MATCHMAKING_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let pg_client = match pg_pool.get().await {
+            Ok(pg_client) => pg_client,
+            Err(err) => {
+                log::error!("Matchmaking skipped (failed to get a client): {err}");
+                continue;
+            }
+        };
+
+        matchmaker.form_matches(&pg_client, &config).await;
+    }
+}
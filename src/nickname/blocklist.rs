@@ -0,0 +1,281 @@
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+
+use actix_web::web;
+use regex::Regex;
+
+use crate::app_data::AppData;
+use crate::config::ApiConfig;
+use crate::errors::Result;
+use crate::nickname::Nickname;
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+const CONTAINS_PREFIX: &str = "contains:";
+const REGEX_PREFIX: &str = "regex:";
+
+#[derive(Default)]
+struct BlocklistEntries {
+    words: Vec<BlocklistEntry>,
+    substrings: Vec<BlocklistEntry>,
+    regexes: Vec<Regex>,
+}
+
+/// Word or part of a nickname, with its nickname key to catch lookalikes from other scripts
+struct BlocklistEntry {
+    folded: String,
+    key: String,
+}
+
+impl BlocklistEntry {
+    fn new(value: &str) -> Self {
+        let folded = fold_leetspeak(value);
+        let key = Nickname::key_of(&folded);
+
+        Self { folded, key }
+    }
+
+    fn form(&self, keyed: bool) -> &str {
+        if keyed { &self.key } else { &self.folded }
+    }
+}
+
+/// List of forbidden nicknames, read from `player_nickname_blocklist`
+///
+/// Each line of the file is an entry:
+/// - `word` forbids a nickname having this word
+/// - `contains:part` forbids a nickname containing this part, even across separators
+/// - `regex:pattern` forbids a nickname matching this pattern
+///
+/// Empty lines and lines starting with `#` are ignored, nicknames are lowercased and leetspeak
+/// is folded (`n00b` => `noob`) before being compared to entries, they are also compared by
+/// their nickname key so that lookalikes from other scripts are forbidden as well.
+/// The file is reloaded by [`watch`] a few seconds after its modification time changes.
+pub struct NicknameBlocklist {
+    path: Option<PathBuf>,
+    state: RwLock<(Option<SystemTime>, BlocklistEntries)>,
+}
+
+impl NicknameBlocklist {
+    pub fn from_config(config: &ApiConfig) -> Result<Self> {
+        let Some(path) = config.player_nickname_blocklist.as_ref().map(PathBuf::from) else {
+            return Ok(Self {
+                path: None,
+                state: RwLock::new((None, BlocklistEntries::default())),
+            });
+        };
+
+        let modified = std::fs::metadata(&path)?.modified()?;
+        let entries = read_entries(&path)?;
+
+        Ok(Self {
+            path: Some(path),
+            state: RwLock::new((Some(modified), entries)),
+        })
+    }
+
+    /// Returns the entry forbidding the nickname if any
+    pub fn find_match(&self, nickname: &str) -> Option<String> {
+        let folded = fold_leetspeak(nickname);
+        let key = Nickname::key_of(&folded);
+
+        let state = self.state.read().unwrap_or_else(|err| err.into_inner());
+        let entries = &state.1;
+
+        for keyed in [false, true] {
+            let form = if keyed { &key } else { &folded };
+            let joined: String = form.chars().filter(|&c| !is_separator(c)).collect();
+
+            if let Some(entry) = entries.words.iter().find(|&entry| {
+                form.split(is_separator)
+                    .any(|word| word == entry.form(keyed))
+            }) {
+                return Some(entry.folded.clone());
+            }
+
+            if let Some(entry) = entries
+                .substrings
+                .iter()
+                .find(|&entry| joined.contains(entry.form(keyed)))
+            {
+                return Some(format!("{CONTAINS_PREFIX}{}", entry.folded));
+            }
+
+            if let Some(regex) = entries.regexes.iter().find(|regex| regex.is_match(form)) {
+                return Some(format!("{REGEX_PREFIX}{}", regex.as_str()));
+            }
+        }
+
+        None
+    }
+
+    fn reload_if_modified(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let modified = match std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(err) => {
+                log::error!(
+                    "Failed to read nickname blocklist {} metadata: {err}",
+                    path.display()
+                );
+                return;
+            }
+        };
+
+        {
+            let state = self.state.read().unwrap_or_else(|err| err.into_inner());
+            if state.0 == Some(modified) {
+                return;
+            }
+        }
+
+        match read_entries(path) {
+            Ok(entries) => {
+                log::info!("Reloaded nickname blocklist {}", path.display());
+                *self.state.write().unwrap_or_else(|err| err.into_inner()) =
+                    (Some(modified), entries);
+            }
+            Err(err) => {
+                // keep the previous entries, a partially written file shouldn't disable the blocklist
+                log::error!(
+                    "Failed to reload nickname blocklist {}: {err:?}",
+                    path.display()
+                );
+            }
+        }
+    }
+}
+
+/// Periodically reloads the blocklist file if it was modified, away from the request handlers
+pub async fn watch(app_data: web::Data<AppData>) {
+    if app_data.nickname_blocklist.path.is_none() {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let app_data = app_data.clone();
+        if let Err(err) =
+            tokio::task::spawn_blocking(move || app_data.nickname_blocklist.reload_if_modified())
+                .await
+        {
+            log::error!("Failed to reload the nickname blocklist: {err}");
+        }
+    }
+}
+
+fn read_entries(path: &Path) -> Result<BlocklistEntries> {
+    let content = std::fs::read_to_string(path)?;
+
+    let mut entries = BlocklistEntries::default();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(pattern) = line.strip_prefix(REGEX_PREFIX) {
+            match Regex::new(pattern.trim()) {
+                Ok(regex) => entries.regexes.push(regex),
+                Err(err) => {
+                    log::error!("Ignoring invalid nickname blocklist regex {pattern}: {err}")
+                }
+            }
+        } else if let Some(substring) = line.strip_prefix(CONTAINS_PREFIX) {
+            entries
+                .substrings
+                .push(BlocklistEntry::new(substring.trim()));
+        } else {
+            entries.words.push(BlocklistEntry::new(line));
+        }
+    }
+
+    Ok(entries)
+}
+
+fn fold_leetspeak(value: &str) -> String {
+    value
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | '!' | '|' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '6' | '9' => 'g',
+            '7' => 't',
+            '8' => 'b',
+            c => c,
+        })
+        .collect()
+}
+
+fn is_separator(char: char) -> bool {
+    char == ' ' || char == '_' || char == '-' || char == '.'
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock;
+
+    use regex::Regex;
+
+    use super::{BlocklistEntries, BlocklistEntry, NicknameBlocklist};
+
+    fn blocklist(words: &[&str], substrings: &[&str], regexes: &[&str]) -> NicknameBlocklist {
+        let entries = BlocklistEntries {
+            words: words.iter().map(|word| BlocklistEntry::new(word)).collect(),
+            substrings: substrings
+                .iter()
+                .map(|part| BlocklistEntry::new(part))
+                .collect(),
+            regexes: regexes
+                .iter()
+                .map(|regex| Regex::new(regex).unwrap())
+                .collect(),
+        };
+
+        NicknameBlocklist {
+            path: None,
+            state: RwLock::new((None, entries)),
+        }
+    }
+
+    #[test]
+    fn matches_folded_nicknames() {
+        let blocklist = blocklist(&["noob"], &["admin"], &["^mod"]);
+
+        assert_eq!(blocklist.find_match("the N00b"), Some("noob".to_string()));
+        assert_eq!(blocklist.find_match("noobs"), None);
+        assert_eq!(
+            blocklist.find_match("4d_min"),
+            Some("contains:admin".to_string())
+        );
+        assert_eq!(
+            blocklist.find_match("moderator"),
+            Some("regex:^mod".to_string())
+        );
+        assert_eq!(blocklist.find_match("alice"), None);
+    }
+
+    #[test]
+    fn matches_lookalikes_from_other_scripts() {
+        let blocklist = blocklist(&["noob"], &["admin"], &[]);
+
+        assert_eq!(blocklist.find_match("the nооb"), Some("noob".to_string())); // cyrillic o
+        assert_eq!(
+            blocklist.find_match("аdmin"),
+            Some("contains:admin".to_string())
+        ); // cyrillic a
+        assert_eq!(
+            blocklist.find_match("adrnin"),
+            Some("contains:admin".to_string())
+        );
+    }
+}
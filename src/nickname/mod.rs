@@ -0,0 +1,192 @@
+pub mod blocklist;
+
+use deadpool_postgres::tokio_postgres::error::SqlState;
+use deadpool_postgres::tokio_postgres::types::Type;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::{GeneralSecurityProfile, MixedScript, skeleton};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::config::ApiConfig;
+use crate::errors::api::RouteError;
+use crate::errors::codes::ServerErrorCode;
+use crate::nickname::blocklist::NicknameBlocklist;
+
+#[derive(Debug)]
+pub struct Nickname {
+    /// NFKC normalized nickname, as displayed to the players
+    pub display: String,
+    /// Case and confusable insensitive form, used to prevent lookalike nicknames
+    pub key: String,
+}
+
+impl Nickname {
+    pub fn validate(
+        config: &ApiConfig,
+        blocklist: &NicknameBlocklist,
+        nickname: &str,
+    ) -> Result<Self, RouteError> {
+        let display = nickname.nfkc().collect::<String>().trim().to_string();
+
+        if display.is_empty() {
+            return Err(RouteError::InvalidRequest(
+                ServerErrorCode::NicknameEmpty,
+                "Nickname cannot be empty".to_string(),
+            ));
+        }
+
+        if display.graphemes(true).count() > config.player_nickname_maxlength {
+            return Err(RouteError::InvalidRequest(
+                ServerErrorCode::NicknameToolong,
+                format!(
+                    "Nickname size exceeds maximum size of {}",
+                    config.player_nickname_maxlength
+                ),
+            ));
+        }
+
+        if !config.player_allow_non_ascii {
+            if let Some(char) = display
+                .chars()
+                .find(|&x| !x.is_ascii_alphanumeric() && !is_separator(x))
+            {
+                return Err(RouteError::InvalidRequest(
+                    ServerErrorCode::NicknameForbiddenCharacters,
+                    format!("Nickname can only have ascii characters (invalid character {char})"),
+                ));
+            }
+        } else {
+            if let Some(char) = display
+                .chars()
+                .find(|&x| !x.identifier_allowed() && !is_separator(x))
+            {
+                return Err(RouteError::InvalidRequest(
+                    ServerErrorCode::NicknameForbiddenCharacters,
+                    format!("Nickname has a forbidden character ({char})"),
+                ));
+            }
+
+            if !display.as_str().is_single_script() {
+                return Err(RouteError::InvalidRequest(
+                    ServerErrorCode::NicknameMixedScripts,
+                    "Nickname cannot mix characters from different scripts".to_string(),
+                ));
+            }
+        }
+
+        if let Some(entry) = blocklist.find_match(&display) {
+            return Err(RouteError::InvalidRequest(
+                ServerErrorCode::NicknameForbidden(entry),
+                "Nickname is not allowed, please change it".to_string(),
+            ));
+        }
+
+        let key = Self::key_of(&display);
+
+        Ok(Self { display, key })
+    }
+
+    /// Computes the key of an already normalized nickname
+    pub fn key_of(nickname: &str) -> String {
+        skeleton(&nickname.to_lowercase())
+            .collect::<String>()
+            .to_lowercase()
+    }
+}
+
+/// Keys the nicknames the migration left without a key, as it only keys the ascii nicknames
+///
+/// A player whose key is taken by another player is keyed by their nickname until renamed.
+pub async fn backfill_keys(
+    pg_client: &deadpool_postgres::Client,
+) -> Result<(), tokio_postgres::Error> {
+    for table in ["players", "player_nickname_history"] {
+        backfill_table_keys(pg_client, table).await?;
+    }
+
+    Ok(())
+}
+
+async fn backfill_table_keys(
+    pg_client: &deadpool_postgres::Client,
+    table: &str,
+) -> Result<(), tokio_postgres::Error> {
+    let find_keys_statement = pg_client
+        .prepare_typed_cached(
+            &format!("SELECT id, nickname FROM {table} WHERE nickname_key IS NULL"),
+            &[],
+        )
+        .await?;
+
+    let update_key_statement = pg_client
+        .prepare_typed_cached(
+            &format!("UPDATE {table} SET nickname_key = $2 WHERE id = $1"),
+            &[Type::INT4, Type::VARCHAR],
+        )
+        .await?;
+
+    let rows = pg_client.query(&find_keys_statement, &[]).await?;
+    for row in &rows {
+        let id: i32 = row.try_get(0)?;
+        let nickname: &str = row.try_get(1)?;
+
+        let key = Nickname::key_of(nickname);
+        match pg_client.execute(&update_key_statement, &[&id, &key]).await {
+            Ok(_) => {}
+            Err(err) if err.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+                log::warn!(
+                    "Nickname {nickname} ({table} {id}) is keyed by itself as {key} is taken, it should be renamed"
+                );
+                pg_client
+                    .execute(&update_key_statement, &[&id, &nickname])
+                    .await?;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    if !rows.is_empty() {
+        log::info!("Backfilled {} nickname keys of {table}", rows.len());
+    }
+
+    Ok(())
+}
+
+fn is_separator(char: char) -> bool {
+    char == ' ' || char == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Nickname;
+
+    #[test]
+    fn key_ignores_case_and_lookalikes() {
+        assert_eq!(Nickname::key_of("Admin"), Nickname::key_of("admin"));
+        assert_eq!(Nickname::key_of("admin"), Nickname::key_of("adrnin"));
+        assert_eq!(Nickname::key_of("paypal"), Nickname::key_of("pаypal")); // cyrillic a
+        assert_eq!(Nickname::key_of("l0l"), Nickname::key_of("lol"));
+        assert_ne!(Nickname::key_of("alice"), Nickname::key_of("bob"));
+    }
+
+    #[test]
+    fn ascii_keys_match_the_migration() {
+        // database/05_nickname_keys.sql keys the ascii nicknames by lowercasing them and replacing m, 0 and 1
+        let migration_key = |nickname: &str| {
+            nickname
+                .to_lowercase()
+                .replace('m', "rn")
+                .replace('0', "o")
+                .replace('1', "l")
+        };
+
+        for char in ('a'..='z')
+            .chain('A'..='Z')
+            .chain('0'..='9')
+            .chain([' ', '_'])
+        {
+            let nickname = format!("x{char}x");
+            assert_eq!(Nickname::key_of(&nickname), migration_key(&nickname));
+        }
+        assert_eq!(Nickname::key_of("Adm1n_0ne"), migration_key("Adm1n_0ne"));
+    }
+}
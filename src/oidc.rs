@@ -0,0 +1,545 @@
+use std::time::{Duration, Instant};
+
+use base64::prelude::*;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use rand_core::{CryptoRng, RngCore};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use url::Url;
+
+use crate::config::ApiConfig;
+use crate::errors::api::{ErrorCause, RouteError};
+use crate::errors::codes::ServerErrorCode;
+
+const DISCOVERY_PATH: &str = ".well-known/openid-configuration";
+// provider keys are rotated rarely, unknown key ids trigger a refresh anyway
+const METADATA_LIFESPAN: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+struct CachedMetadata {
+    fetch_time: Instant,
+    metadata: ProviderMetadata,
+    jwks: JwkSet,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    preferred_username: Option<String>,
+}
+
+/// Identity of a player on the OIDC provider
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub preferred_username: Option<String>,
+}
+
+/// Secrets of a login attempt, kept by the API until the player comes back with a code
+pub struct OidcLogin {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+impl OidcLogin {
+    pub fn generate<R>(mut rng: R) -> crate::errors::Result<Self>
+    where
+        R: CryptoRng + RngCore,
+    {
+        let mut random = || -> crate::errors::Result<String> {
+            let mut bytes = [0u8; 32];
+            rng.try_fill_bytes(&mut bytes)?;
+            Ok(BASE64_URL_SAFE_NO_PAD.encode(bytes))
+        };
+
+        Ok(Self {
+            state: random()?,
+            nonce: random()?,
+            code_verifier: random()?,
+        })
+    }
+}
+
+/// Client of the OpenID Connect provider set by `oidc_issuer`, using the authorization code flow
+/// with PKCE
+///
+/// Any provider implementing the discovery document works, including a local stand-in server
+/// (`oidc_issuer = "http://localhost:8080"`) for tests.
+pub struct OidcClient {
+    http: reqwest::Client,
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    scopes: String,
+    metadata: RwLock<Option<CachedMetadata>>,
+}
+
+impl OidcClient {
+    pub fn from_config(config: &ApiConfig) -> Option<Self> {
+        let issuer = config.oidc_issuer.as_ref()?;
+
+        Some(Self {
+            http: reqwest::Client::new(),
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id: config.oidc_client_id.clone(),
+            client_secret: config.oidc_client_secret.unsecure().to_string(),
+            redirect_uri: config.oidc_redirect_uri.clone(),
+            scopes: config.oidc_scopes.clone(),
+            metadata: RwLock::new(None),
+        })
+    }
+
+    pub async fn authorization_url(&self, login: &OidcLogin) -> Result<String, RouteError> {
+        let authorization_endpoint = {
+            self.refresh_metadata(false).await?;
+            let metadata = self.metadata.read().await;
+            metadata
+                .as_ref()
+                .map(|cached| cached.metadata.authorization_endpoint.clone())
+                .unwrap_or_default()
+        };
+
+        let mut url = Url::parse(&authorization_endpoint).map_err(provider_error)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", &self.scopes)
+            .append_pair("state", &login.state)
+            .append_pair("nonce", &login.nonce)
+            .append_pair("code_challenge", &code_challenge(&login.code_verifier))
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url.into())
+    }
+
+    /// Exchanges the authorization code for an ID token and returns the identity it proves
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        login: &OidcLogin,
+    ) -> Result<OidcIdentity, RouteError> {
+        let token_endpoint = {
+            self.refresh_metadata(false).await?;
+            let metadata = self.metadata.read().await;
+            metadata
+                .as_ref()
+                .map(|cached| cached.metadata.token_endpoint.clone())
+                .unwrap_or_default()
+        };
+
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", "authorization_code")
+            .append_pair("code", code)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("client_id", &self.client_id)
+            .append_pair("client_secret", &self.client_secret)
+            .append_pair("code_verifier", &login.code_verifier)
+            .finish();
+
+        let response = self
+            .http
+            .post(token_endpoint)
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                "application/x-www-form-urlencoded",
+            )
+            .body(body)
+            .send()
+            .await
+            .map_err(provider_error)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(RouteError::InvalidRequest(
+                ServerErrorCode::OidcAuthenticationFailed,
+                format!("OIDC provider refused the authorization code ({status})"),
+            ));
+        }
+
+        let token_response: TokenResponse = parse_json(response).await?;
+        let claims = self.validate_id_token(&token_response.id_token).await?;
+
+        if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+            return Err(RouteError::InvalidRequest(
+                ServerErrorCode::OidcAuthenticationFailed,
+                "ID token nonce doesn't match the login".to_string(),
+            ));
+        }
+
+        Ok(OidcIdentity {
+            issuer: self.issuer.clone(),
+            subject: claims.sub,
+            preferred_username: claims.preferred_username,
+        })
+    }
+
+    async fn validate_id_token(&self, id_token: &str) -> Result<IdTokenClaims, RouteError> {
+        let invalid_id_token = |err: jsonwebtoken::errors::Error| {
+            RouteError::InvalidRequest(
+                ServerErrorCode::OidcAuthenticationFailed,
+                format!("Invalid ID token: {err}"),
+            )
+        };
+
+        let header = decode_header(id_token).map_err(invalid_id_token)?;
+
+        // the client secret isn't used to verify ID tokens, only the provider keys are trusted
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(RouteError::InvalidRequest(
+                ServerErrorCode::OidcAuthenticationFailed,
+                format!(
+                    "ID token signed with unsupported algorithm {:?}",
+                    header.alg
+                ),
+            ));
+        }
+
+        let kid = header.kid.unwrap_or_default();
+
+        let mut decoding_key = self.find_key(&kid).await?;
+        if decoding_key.is_none() {
+            // the provider may have rotated its keys
+            self.refresh_metadata(true).await?;
+            decoding_key = self.find_key(&kid).await?;
+        }
+
+        let Some(decoding_key) = decoding_key else {
+            return Err(RouteError::InvalidRequest(
+                ServerErrorCode::OidcAuthenticationFailed,
+                format!("ID token signed with unknown key '{kid}'"),
+            ));
+        };
+
+        let issuer = {
+            let metadata = self.metadata.read().await;
+            metadata
+                .as_ref()
+                .map(|cached| cached.metadata.issuer.clone())
+                .unwrap_or_default()
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[issuer]);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let token = decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+            .map_err(invalid_id_token)?;
+
+        Ok(token.claims)
+    }
+
+    async fn find_key(&self, kid: &str) -> Result<Option<DecodingKey>, RouteError> {
+        let metadata = self.metadata.read().await;
+        let Some(jwk) = metadata.as_ref().and_then(|cached| {
+            if kid.is_empty() && cached.jwks.keys.len() == 1 {
+                cached.jwks.keys.first()
+            } else {
+                cached.jwks.find(kid)
+            }
+        }) else {
+            return Ok(None);
+        };
+
+        DecodingKey::from_jwk(jwk).map(Some).map_err(|err| {
+            RouteError::ServerError(
+                ErrorCause::Internal,
+                ServerErrorCode::External(format!("Invalid OIDC provider key '{kid}': {err}")),
+            )
+        })
+    }
+
+    async fn refresh_metadata(&self, force: bool) -> Result<(), RouteError> {
+        {
+            let metadata = self.metadata.read().await;
+            if !force
+                && metadata
+                    .as_ref()
+                    .is_some_and(|cached| cached.fetch_time.elapsed() < METADATA_LIFESPAN)
+            {
+                return Ok(());
+            }
+        }
+
+        let response = self
+            .http
+            .get(format!("{}/{DISCOVERY_PATH}", self.issuer))
+            .send()
+            .await
+            .map_err(provider_error)?;
+        let metadata: ProviderMetadata = parse_json(response).await?;
+
+        if metadata.issuer.trim_end_matches('/') != self.issuer {
+            return Err(RouteError::ServerError(
+                ErrorCause::Internal,
+                ServerErrorCode::External(format!(
+                    "OIDC provider announces the issuer {} instead of {}",
+                    metadata.issuer, self.issuer
+                )),
+            ));
+        }
+
+        let response = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .map_err(provider_error)?;
+        let jwks: JwkSet = parse_json(response).await?;
+
+        *self.metadata.write().await = Some(CachedMetadata {
+            fetch_time: Instant::now(),
+            metadata,
+            jwks,
+        });
+
+        Ok(())
+    }
+}
+
+fn code_challenge(code_verifier: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+async fn parse_json<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, RouteError> {
+    let bytes = response
+        .error_for_status()
+        .map_err(provider_error)?
+        .bytes()
+        .await
+        .map_err(provider_error)?;
+
+    serde_json::from_slice(&bytes).map_err(provider_error)
+}
+
+fn provider_error(err: impl ToString) -> RouteError {
+    RouteError::ServerError(
+        ErrorCause::Internal,
+        ServerErrorCode::External(format!("OIDC provider error: {}", err.to_string())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use actix_web::{App, HttpResponse, HttpServer, get, post, web};
+    use ed25519_dalek::SigningKey;
+    use ed25519_dalek::pkcs8::EncodePrivateKey;
+    use jsonwebtoken::jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, OctetKeyPairParameters,
+        OctetKeyPairType,
+    };
+    use jsonwebtoken::{EncodingKey, Header};
+    use rand_core::OsRng;
+    use serde::Serialize;
+
+    use super::*;
+
+    const KID: &str = "provider-key";
+
+    /// Stand-in OIDC provider, the codes are issued by the test instead of a login page
+    struct MockProvider {
+        issuer: String,
+        signing_key: SigningKey,
+        /// authorization code => (nonce, code challenge)
+        codes: Mutex<HashMap<String, (String, String)>>,
+    }
+
+    #[derive(Serialize)]
+    struct MockClaims<'a> {
+        iss: &'a str,
+        aud: &'a str,
+        sub: &'a str,
+        exp: u64,
+        nonce: &'a str,
+        preferred_username: &'a str,
+    }
+
+    #[get("/.well-known/openid-configuration")]
+    async fn discovery(provider: web::Data<MockProvider>) -> HttpResponse {
+        HttpResponse::Ok().json(serde_json::json!({
+            "issuer": provider.issuer,
+            "authorization_endpoint": format!("{}/authorize", provider.issuer),
+            "token_endpoint": format!("{}/token", provider.issuer),
+            "jwks_uri": format!("{}/jwks", provider.issuer),
+        }))
+    }
+
+    #[get("/jwks")]
+    async fn jwks(provider: web::Data<MockProvider>) -> HttpResponse {
+        let jwk = Jwk {
+            common: CommonParameters {
+                key_id: Some(KID.to_string()),
+                ..Default::default()
+            },
+            algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: BASE64_URL_SAFE_NO_PAD.encode(provider.signing_key.verifying_key().as_bytes()),
+            }),
+        };
+        HttpResponse::Ok().json(JwkSet { keys: vec![jwk] })
+    }
+
+    #[post("/token")]
+    async fn token(
+        provider: web::Data<MockProvider>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> HttpResponse {
+        let Some((nonce, challenge)) = provider.codes.lock().unwrap().remove(&form["code"]) else {
+            return HttpResponse::BadRequest().finish();
+        };
+
+        if form["grant_type"] != "authorization_code"
+            || form["client_id"] != "this_api_of_mine"
+            || code_challenge(&form["code_verifier"]) != challenge
+        {
+            return HttpResponse::BadRequest().finish();
+        }
+
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(KID.to_string());
+        let key = EncodingKey::from_ed_der(provider.signing_key.to_pkcs8_der().unwrap().as_bytes());
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60;
+        let claims = MockClaims {
+            iss: &provider.issuer,
+            aud: "this_api_of_mine",
+            sub: "subject-1",
+            exp,
+            nonce: &nonce,
+            preferred_username: "Alice",
+        };
+
+        HttpResponse::Ok().json(serde_json::json!({
+            "id_token": jsonwebtoken::encode(&header, &claims, &key).unwrap(),
+        }))
+    }
+
+    async fn start_provider() -> (web::Data<MockProvider>, OidcClient) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let provider = web::Data::new(MockProvider {
+            issuer: issuer.clone(),
+            signing_key: SigningKey::from_bytes(&[7; 32]),
+            codes: Mutex::new(HashMap::new()),
+        });
+
+        let app_provider = provider.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_provider.clone())
+                .service(discovery)
+                .service(jwks)
+                .service(token)
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        actix_web::rt::spawn(server);
+
+        let config = ApiConfig {
+            oidc_issuer: Some(format!("{issuer}/")),
+            ..ApiConfig::default()
+        };
+
+        (provider, OidcClient::from_config(&config).unwrap())
+    }
+
+    /// Plays the login page of the provider, returns the code given back to the player
+    fn log_in(provider: &MockProvider, authorization_url: &str) -> String {
+        let url = Url::parse(authorization_url).unwrap();
+        let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(query["code_challenge_method"], "S256");
+        assert_eq!(query["response_type"], "code");
+
+        let code = format!("code-{}", query["state"]);
+        provider.codes.lock().unwrap().insert(
+            code.clone(),
+            (query["nonce"].clone(), query["code_challenge"].clone()),
+        );
+        code
+    }
+
+    #[actix_web::test]
+    async fn code_exchange_gives_the_identity() {
+        let (provider, client) = start_provider().await;
+        let login = OidcLogin::generate(OsRng).unwrap();
+
+        let authorization_url = client.authorization_url(&login).await.unwrap();
+        assert!(authorization_url.starts_with(&format!("{}/authorize?", provider.issuer)));
+        let code = log_in(&provider, &authorization_url);
+
+        let identity = client.exchange_code(&code, &login).await.unwrap();
+        assert_eq!(identity.issuer, provider.issuer);
+        assert_eq!(identity.subject, "subject-1");
+        assert_eq!(identity.preferred_username.as_deref(), Some("Alice"));
+
+        // a code can only be exchanged once
+        assert!(client.exchange_code(&code, &login).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn code_exchange_checks_the_nonce_and_verifier() {
+        let (provider, client) = start_provider().await;
+        let login = OidcLogin::generate(OsRng).unwrap();
+        let code = log_in(&provider, &client.authorization_url(&login).await.unwrap());
+
+        // an ID token issued for another login is refused
+        let other_login = OidcLogin {
+            nonce: "other nonce".to_string(),
+            ..OidcLogin::generate(OsRng).unwrap()
+        };
+        provider.codes.lock().unwrap().insert(
+            "replayed".to_string(),
+            (
+                "stolen nonce".to_string(),
+                code_challenge(&other_login.code_verifier),
+            ),
+        );
+        let Err(RouteError::InvalidRequest(ServerErrorCode::OidcAuthenticationFailed, message)) =
+            client.exchange_code("replayed", &other_login).await
+        else {
+            panic!("an ID token with another nonce was accepted");
+        };
+        assert!(message.contains("nonce"), "{message}");
+
+        // the provider refuses a code without the verifier of its login
+        let Err(RouteError::InvalidRequest(ServerErrorCode::OidcAuthenticationFailed, message)) =
+            client.exchange_code(&code, &other_login).await
+        else {
+            panic!("a code was exchanged without its verifier");
+        };
+        assert!(message.contains("refused"), "{message}");
+    }
+}
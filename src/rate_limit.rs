@@ -0,0 +1,303 @@
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::http::header::{AUTHORIZATION, HeaderMap};
+use actix_web::middleware::Next;
+use actix_web::web;
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultKeyedRateLimiter, Quota};
+use ipnet::IpNet;
+use secure_string::SecureString;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::app_data::AppData;
+use crate::client_ip::{client_ip, quota_key};
+use crate::config::{ApiConfig, RateLimitKey, RateLimitPolicy};
+use crate::data::game_data_token::GameDataToken;
+use crate::errors::api::{ErrorCause, RouteError};
+use crate::errors::codes::ServerErrorCode;
+use crate::game_api_keys::GameApiKeys;
+use crate::routes::game_server::token_validation;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum ClientKey {
+    Ip(IpAddr),
+    Admin,
+    /// player of a valid game API token
+    Player(Uuid),
+}
+
+struct Policy {
+    scope: String,
+    methods: Vec<Method>,
+    key: RateLimitKey,
+    limiter: DefaultKeyedRateLimiter<ClientKey>,
+}
+
+impl Policy {
+    fn from_config(policy: &RateLimitPolicy) -> Result<Self, String> {
+        let burst = NonZeroU32::new(policy.burst)
+            .ok_or_else(|| format!("rate limit burst of {} must not be zero", policy.scope))?;
+        let quota = Quota::with_period(policy.period)
+            .ok_or_else(|| format!("rate limit period of {} must not be zero", policy.scope))?
+            .allow_burst(burst);
+
+        let methods = policy
+            .methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.to_uppercase().as_bytes())
+                    .map_err(|_| format!("invalid rate limit method {method}"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            scope: policy.scope.clone(),
+            methods,
+            key: policy.key,
+            limiter: DefaultKeyedRateLimiter::keyed(quota),
+        })
+    }
+
+    fn applies_to(&self, method: &Method, path: &str) -> bool {
+        if !self.methods.is_empty() && !self.methods.contains(method) {
+            return false;
+        }
+
+        // "/v1/players" applies to "/v1/players/..." but not to "/v1/playersfoo"
+        path.strip_prefix(self.scope.as_str()).is_some_and(|rest| {
+            rest.is_empty() || rest.starts_with('/') || self.scope.ends_with('/')
+        })
+    }
+}
+
+/// Rate limits declared by `rate_limits`
+///
+/// Only the policies having the most specific scope matching a request apply to it, so the
+/// routes with their own policy aren't also limited by the catch-all ones.
+pub struct RateLimiter {
+    policies: Vec<Policy>,
+    trusted_proxies: Vec<IpNet>,
+    admin_api_key: Option<SecureString>,
+}
+
+impl RateLimiter {
+    pub fn from_config(config: &ApiConfig) -> Result<Self, String> {
+        Ok(Self {
+            policies: config
+                .rate_limits
+                .iter()
+                .map(Policy::from_config)
+                .collect::<Result<_, _>>()?,
+            trusted_proxies: config.trusted_proxies.clone(),
+            admin_api_key: config.admin_api_key.clone(),
+        })
+    }
+
+    fn policies_for<'a>(
+        &'a self,
+        method: &'a Method,
+        path: &'a str,
+    ) -> impl Iterator<Item = &'a Policy> {
+        let scope_length = self
+            .policies
+            .iter()
+            .filter(|policy| policy.applies_to(method, path))
+            .map(|policy| policy.scope.len())
+            .max();
+
+        self.policies.iter().filter(move |policy| {
+            Some(policy.scope.len()) == scope_length && policy.applies_to(method, path)
+        })
+    }
+
+    /// Finds who sent the request from credentials which can be checked without the database
+    ///
+    /// Unverified credentials are ignored, otherwise any value would get its own quota.
+    fn identity(
+        &self,
+        headers: &HeaderMap,
+        game_api_keys: Option<&GameApiKeys>,
+    ) -> Option<ClientKey> {
+        let credentials = headers
+            .get(AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")?;
+
+        if let Some(admin_api_key) = &self.admin_api_key
+            && bool::from(
+                credentials
+                    .as_bytes()
+                    .ct_eq(admin_api_key.unsecure().as_bytes()),
+            )
+        {
+            return Some(ClientKey::Admin);
+        }
+
+        let token = game_api_keys?
+            .decode::<GameDataToken>(credentials, token_validation())
+            .ok()?;
+
+        Some(ClientKey::Player(token.claims.player_uuid))
+    }
+
+    fn check(&self, req: &ServiceRequest) -> Result<(), RouteError> {
+        let ip = client_ip(req.peer_addr(), req.headers(), &self.trusted_proxies).map(quota_key);
+
+        for policy in self.policies_for(req.method(), req.path()) {
+            let identity = match policy.key {
+                RateLimitKey::Ip => None,
+                RateLimitKey::Identity => self.identity(
+                    req.headers(),
+                    req.app_data::<web::Data<AppData>>()
+                        .map(|app_data| &app_data.game_api_keys),
+                ),
+            };
+
+            let Some(key) = identity.or(ip.map(ClientKey::Ip)) else {
+                return Err(RouteError::ServerError(
+                    ErrorCause::Internal,
+                    ServerErrorCode::External(
+                        "Could not extract the client IP address".to_string(),
+                    ),
+                ));
+            };
+
+            if let Err(not_until) = policy.limiter.check_key(&key) {
+                let wait_time = not_until.wait_time_from(DefaultClock::default().now());
+                return Err(RouteError::InvalidRequest(
+                    ServerErrorCode::RateLimited(wait_time.as_secs_f64().ceil() as u64),
+                    format!("Too many requests on {}", policy.scope),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the clients which are back to a full burst
+    pub fn purge(&self) {
+        for policy in &self.policies {
+            policy.limiter.retain_recent();
+            policy.limiter.shrink_to_fit();
+        }
+    }
+}
+
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if let Some(rate_limiter) = req.app_data::<web::Data<RateLimiter>>() {
+        rate_limiter.check(&req)?;
+    }
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::http::header::HeaderValue;
+
+    use super::*;
+
+    fn rate_limiter() -> RateLimiter {
+        let mut config = ApiConfig {
+            admin_api_key: Some("admin key".into()),
+            ..ApiConfig::default()
+        };
+        config.rate_limits.push(RateLimitPolicy {
+            scope: "/v1/players".to_string(),
+            methods: vec!["delete".to_string()],
+            key: RateLimitKey::Ip,
+            period: Duration::from_secs(1),
+            burst: 1,
+        });
+
+        RateLimiter::from_config(&config).unwrap()
+    }
+
+    fn scopes(rate_limiter: &RateLimiter, method: Method, path: &str) -> Vec<String> {
+        rate_limiter
+            .policies_for(&method, path)
+            .map(|policy| format!("{} {:?}", policy.scope, policy.methods))
+            .collect()
+    }
+
+    #[test]
+    fn scope_matches_whole_path_segments() {
+        let rate_limiter = rate_limiter();
+        let policy = &rate_limiter.policies[1];
+        assert_eq!(policy.scope, "/v1/players");
+
+        assert!(policy.applies_to(&Method::POST, "/v1/players"));
+        assert!(policy.applies_to(&Method::POST, "/v1/players/sub"));
+        assert!(!policy.applies_to(&Method::POST, "/v1/playersfoo"));
+        assert!(!policy.applies_to(&Method::GET, "/v1/players"));
+        assert!(rate_limiter.policies[0].applies_to(&Method::GET, "/anything"));
+    }
+
+    #[test]
+    fn most_specific_scope_wins() {
+        let rate_limiter = rate_limiter();
+
+        assert_eq!(
+            scopes(&rate_limiter, Method::POST, "/game_server/v1/refresh"),
+            ["/game_server []"]
+        );
+        assert_eq!(
+            scopes(&rate_limiter, Method::POST, "/v1/players"),
+            ["/v1/players [POST]"]
+        );
+        assert_eq!(
+            scopes(&rate_limiter, Method::DELETE, "/v1/players"),
+            ["/v1/players [DELETE]"]
+        );
+        // the policies of a more specific scope don't cover the other methods
+        assert_eq!(scopes(&rate_limiter, Method::GET, "/v1/players"), ["/ []"]);
+        assert_eq!(scopes(&rate_limiter, Method::GET, "/v1/version"), ["/ []"]);
+    }
+
+    #[test]
+    fn identity_requires_valid_credentials() {
+        let rate_limiter = rate_limiter();
+        let game_api_keys = GameApiKeys::from_config(&ApiConfig::default()).unwrap();
+
+        let identity = |credentials: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(credentials).unwrap());
+            rate_limiter.identity(&headers, Some(&game_api_keys))
+        };
+
+        let player_uuid = Uuid::new_v4();
+        let token = game_api_keys
+            .encode(&GameDataToken::new_access(
+                1,
+                player_uuid,
+                Duration::from_secs(60),
+                false,
+                Uuid::new_v4(),
+            ))
+            .unwrap();
+
+        assert_eq!(
+            identity(&format!("Bearer {token}")),
+            Some(ClientKey::Player(player_uuid))
+        );
+        assert_eq!(identity("Bearer admin key"), Some(ClientKey::Admin));
+        assert_eq!(identity("Bearer random"), None);
+        assert_eq!(identity(&format!("Bearer {token}x")), None);
+        assert_eq!(identity("admin key"), None);
+        assert_eq!(
+            rate_limiter.identity(&HeaderMap::new(), Some(&game_api_keys)),
+            None
+        );
+    }
+}
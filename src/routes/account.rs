@@ -0,0 +1,306 @@
+use actix_web::{HttpResponse, Responder, post, web};
+use deadpool_postgres::tokio_postgres::types::Type;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::ApiConfig;
+use crate::errors::api::RouteError;
+use crate::errors::codes::ServerErrorCode;
+use crate::routes::players::{find_player_token, validate_player_token};
+
+#[derive(Deserialize)]
+struct AccountParams {
+    token: String,
+}
+
+#[derive(Serialize)]
+struct DeletionResponse {
+    deletion_timestamp: i64,
+}
+
+#[post("/v1/player/delete")]
+async fn delete(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<AccountParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
+
+    let delete_player_statement = pg_client
+        .prepare_typed_cached(
+            "UPDATE players SET deletion_time = NOW() WHERE id = $1 RETURNING EXTRACT(EPOCH FROM NOW() + make_interval(secs => $2))::int8",
+            &[Type::INT4, Type::FLOAT8],
+        )
+        .await?;
+
+    let deletion_result = pg_client
+        .query_one(
+            &delete_player_statement,
+            &[
+                &player_id,
+                &config.player_deletion_grace_period.as_secs_f64(),
+            ],
+        )
+        .await?;
+
+    log::info!("Player {player_id} requested the deletion of its account");
+
+    Ok(HttpResponse::Ok().json(DeletionResponse {
+        deletion_timestamp: deletion_result.try_get(0)?,
+    }))
+}
+
+#[post("/v1/player/delete/cancel")]
+async fn cancel_deletion(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<AccountParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let player_token = find_player_token(&pg_client, &config, &params.token).await?;
+
+    if !player_token.pending_deletion {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::PlayerNotPendingDeletion,
+            format!("Player {} is not pending deletion", player_token.player_id),
+        ));
+    }
+
+    let cancel_deletion_statement = pg_client
+        .prepare_typed_cached(
+            "UPDATE players SET deletion_time = NULL WHERE id = $1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    pg_client
+        .execute(&cancel_deletion_statement, &[&player_token.player_id])
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Serialize)]
+struct PlayerExport {
+    uuid: Uuid,
+    nickname: String,
+    creation_timestamp: i64,
+    last_connection_timestamp: Option<i64>,
+    deletion_timestamp: Option<i64>,
+    permissions: Vec<String>,
+    roles: Vec<String>,
+    ships: Vec<ShipExport>,
+    tokens: Vec<TokenExport>,
+    nickname_history: Vec<NicknameHistoryExport>,
+    bans: Vec<BanExport>,
+    identities: Vec<IdentityExport>,
+}
+
+#[derive(Serialize)]
+struct ShipExport {
+    slot: i32,
+    last_update_timestamp: i64,
+    data: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct TokenExport {
+    device_name: Option<String>,
+    creation_timestamp: i64,
+    last_used_timestamp: Option<i64>,
+    expire_timestamp: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct NicknameHistoryExport {
+    nickname: String,
+    change_timestamp: i64,
+}
+
+#[derive(Serialize)]
+struct BanExport {
+    reason: String,
+    start_timestamp: i64,
+    end_timestamp: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct IdentityExport {
+    issuer: String,
+    subject: String,
+    creation_timestamp: i64,
+    last_used_timestamp: Option<i64>,
+}
+
+#[post("/v1/player/export")]
+async fn export(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<AccountParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    // a player waiting for its deletion can still retrieve its data
+    let player_id = find_player_token(&pg_client, &config, &params.token)
+        .await?
+        .player_id;
+
+    let find_player_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT uuid, nickname, EXTRACT(EPOCH FROM creation_time)::int8, EXTRACT(EPOCH FROM last_connection_time)::int8, EXTRACT(EPOCH FROM deletion_time)::int8 FROM players WHERE id = $1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let find_permissions_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT permission FROM player_permissions WHERE player_id = $1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let find_roles_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT r.name FROM player_roles pr JOIN roles r ON r.id = pr.role_id WHERE pr.player_id = $1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let find_ships_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT slot, EXTRACT(EPOCH FROM last_update)::int8, data FROM player_ships WHERE player_id = $1 ORDER BY slot",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let find_tokens_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT device_name, EXTRACT(EPOCH FROM creation_time)::int8, EXTRACT(EPOCH FROM last_used_at)::int8, EXTRACT(EPOCH FROM expires_at)::int8 FROM player_tokens WHERE player_id = $1 ORDER BY creation_time",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let find_nickname_history_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT nickname, EXTRACT(EPOCH FROM change_time)::int8 FROM player_nickname_history WHERE player_id = $1 ORDER BY change_time",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let find_bans_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT reason, EXTRACT(EPOCH FROM start_time)::int8, EXTRACT(EPOCH FROM end_time)::int8 FROM player_bans WHERE player_id = $1 ORDER BY start_time",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let find_identities_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT issuer, subject, EXTRACT(EPOCH FROM creation_time)::int8, EXTRACT(EPOCH FROM last_used_at)::int8 FROM player_identities WHERE player_id = $1 ORDER BY creation_time",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let player_result = pg_client
+        .query_opt(&find_player_statement, &[&player_id])
+        .await?
+        .ok_or(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidId,
+            format!("No player has the id '{player_id}'"),
+        ))?;
+
+    let permissions = pg_client
+        .query(&find_permissions_statement, &[&player_id])
+        .await?
+        .into_iter()
+        .map(|row| row.try_get(0))
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    let roles = pg_client
+        .query(&find_roles_statement, &[&player_id])
+        .await?
+        .into_iter()
+        .map(|row| row.try_get(0))
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    let ships = pg_client
+        .query(&find_ships_statement, &[&player_id])
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(ShipExport {
+                slot: row.try_get(0)?,
+                last_update_timestamp: row.try_get(1)?,
+                data: row.try_get(2)?,
+            })
+        })
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    let tokens = pg_client
+        .query(&find_tokens_statement, &[&player_id])
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(TokenExport {
+                device_name: row.try_get(0)?,
+                creation_timestamp: row.try_get(1)?,
+                last_used_timestamp: row.try_get(2)?,
+                expire_timestamp: row.try_get(3)?,
+            })
+        })
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    let nickname_history = pg_client
+        .query(&find_nickname_history_statement, &[&player_id])
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(NicknameHistoryExport {
+                nickname: row.try_get(0)?,
+                change_timestamp: row.try_get(1)?,
+            })
+        })
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    let bans = pg_client
+        .query(&find_bans_statement, &[&player_id])
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(BanExport {
+                reason: row.try_get(0)?,
+                start_timestamp: row.try_get(1)?,
+                end_timestamp: row.try_get(2)?,
+            })
+        })
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    let identities = pg_client
+        .query(&find_identities_statement, &[&player_id])
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(IdentityExport {
+                issuer: row.try_get(0)?,
+                subject: row.try_get(1)?,
+                creation_timestamp: row.try_get(2)?,
+                last_used_timestamp: row.try_get(3)?,
+            })
+        })
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    Ok(HttpResponse::Ok().json(PlayerExport {
+        uuid: player_result.try_get(0)?,
+        nickname: player_result.try_get(1)?,
+        creation_timestamp: player_result.try_get(2)?,
+        last_connection_timestamp: player_result.try_get(3)?,
+        deletion_timestamp: player_result.try_get(4)?,
+        permissions,
+        roles,
+        ships,
+        tokens,
+        nickname_history,
+        bans,
+        identities,
+    }))
+}
@@ -0,0 +1,1028 @@
+use std::sync::atomic::Ordering;
+
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, get, patch, post, web};
+use deadpool_postgres::tokio_postgres::types::Type;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+use crate::app_data::AppData;
+use crate::client_ip::client_ip;
+use crate::config::ApiConfig;
+use crate::data::challenge::MAX_DIFFICULTY;
+use crate::data::token::Token;
+use crate::errors::api::{ErrorCause, RouteError};
+use crate::errors::codes::ServerErrorCode;
+use crate::nickname::Nickname;
+
+/// Header naming the caller of the admin API in the audit log, the admin key is shared so it's
+/// only declarative and recorded next to the address of the caller
+pub const ADMIN_ACTOR_HEADER: &str = "X-Admin-Actor";
+
+/// Longest actor name kept from [`ADMIN_ACTOR_HEADER`]
+const MAX_ACTOR_LENGTH: usize = 64;
+
+pub fn validate_admin(req: &HttpRequest, config: &ApiConfig) -> Result<(), RouteError> {
+    let Some(admin_api_key) = &config.admin_api_key else {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidAdminCredentials,
+            "Admin API is disabled".to_string(),
+        ));
+    };
+
+    let key = req
+        .headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|str| str.strip_prefix("Bearer "))
+        .ok_or(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidAdminCredentials,
+            "Missing admin credentials".to_string(),
+        ))?;
+
+    if !bool::from(key.as_bytes().ct_eq(admin_api_key.unsecure().as_bytes())) {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidAdminCredentials,
+            "Invalid admin credentials".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Finds the id of a player from its uuid or its nickname
+pub async fn find_player_id(
+    pg_client: &deadpool_postgres::Client,
+    player: &str,
+) -> Result<i32, RouteError> {
+    let player_result = match Uuid::parse_str(player) {
+        Ok(uuid) => {
+            let find_player_statement = pg_client
+                .prepare_typed_cached("SELECT id FROM players WHERE uuid = $1", &[Type::UUID])
+                .await?;
+
+            pg_client
+                .query_opt(&find_player_statement, &[&uuid])
+                .await?
+        }
+        Err(_) => {
+            let find_player_statement = pg_client
+                .prepare_typed_cached(
+                    "SELECT id FROM players WHERE nickname_key = $1",
+                    &[Type::VARCHAR],
+                )
+                .await?;
+
+            let nickname_key = Nickname::key_of(player.nfkc().collect::<String>().trim());
+
+            pg_client
+                .query_opt(&find_player_statement, &[&nickname_key])
+                .await?
+        }
+    }
+    .ok_or(RouteError::InvalidRequest(
+        ServerErrorCode::InvalidId,
+        format!("No player has the uuid or nickname '{player}'"),
+    ))?;
+
+    Ok(player_result.try_get(0)?)
+}
+
+/// Records an admin action, should be called in the transaction doing the action
+pub async fn audit(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    req: &HttpRequest,
+    config: &ApiConfig,
+    action: &str,
+    player_id: Option<i32>,
+    details: serde_json::Value,
+) -> Result<(), RouteError> {
+    let insert_audit_statement = transaction
+        .prepare_typed_cached(
+            "INSERT INTO admin_audit_log(time, actor, action, player_id, details) VALUES(NOW(), $1, $2, $3, $4)",
+            &[Type::VARCHAR, Type::VARCHAR, Type::INT4, Type::JSONB],
+        )
+        .await?;
+
+    let address = client_ip(req.peer_addr(), req.headers(), &config.trusted_proxies)
+        .map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+
+    let name = req
+        .headers()
+        .get(ADMIN_ACTOR_HEADER)
+        .and_then(|header| header.to_str().ok())
+        .map(|name| {
+            name.trim()
+                .chars()
+                .take(MAX_ACTOR_LENGTH)
+                .collect::<String>()
+        })
+        .filter(|name| !name.is_empty());
+
+    let actor = match name {
+        Some(name) => format!("{name} ({address})"),
+        None => address,
+    };
+
+    transaction
+        .execute(
+            &insert_audit_statement,
+            &[&actor, &action, &player_id, &details],
+        )
+        .await?;
+
+    log::info!("Admin {actor} did {action} on player {player_id:?}: {details}");
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct PermissionsResponse {
+    permissions: Vec<String>,
+    roles: Vec<String>,
+}
+
+#[get("/admin/v1/players/{player}/permissions")]
+async fn player_permissions_get(
+    req: HttpRequest,
+    path: web::Path<String>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let pg_client = pg_pool.get().await?;
+    let player_id = find_player_id(&pg_client, &path).await?;
+
+    let get_player_permissions = pg_client
+        .prepare_typed_cached(
+            "SELECT permission FROM player_permissions WHERE player_id = $1 ORDER BY permission",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let get_player_roles = pg_client
+        .prepare_typed_cached(
+            "SELECT r.name FROM player_roles pr JOIN roles r ON r.id = pr.role_id WHERE pr.player_id = $1 ORDER BY r.name",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let permissions = pg_client
+        .query(&get_player_permissions, &[&player_id])
+        .await?
+        .into_iter()
+        .map(|row| row.try_get(0))
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    let roles = pg_client
+        .query(&get_player_roles, &[&player_id])
+        .await?
+        .into_iter()
+        .map(|row| row.try_get(0))
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    Ok(HttpResponse::Ok().json(PermissionsResponse { permissions, roles }))
+}
+
+#[derive(Deserialize)]
+struct GrantPermissionParams {
+    permission: String,
+}
+
+#[post("/admin/v1/players/{player}/permissions")]
+async fn player_permission_grant(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Json<GrantPermissionParams>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let permission = validate_permission(&params.permission)?;
+
+    let mut pg_client = pg_pool.get().await?;
+    let player_id = find_player_id(&pg_client, &path).await?;
+
+    let grant_permission_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO player_permissions(player_id, permission) VALUES($1, $2) ON CONFLICT (player_id, permission) DO NOTHING",
+            &[Type::INT4, Type::VARCHAR],
+        )
+        .await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let granted = transaction
+        .execute(&grant_permission_statement, &[&player_id, &permission])
+        .await?;
+
+    if granted > 0 {
+        audit(
+            &transaction,
+            &req,
+            &config,
+            "grant_permission",
+            Some(player_id),
+            serde_json::json!({ "permission": permission }),
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[delete("/admin/v1/players/{player}/permissions/{permission}")]
+async fn player_permission_revoke(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let (player, permission) = path.into_inner();
+
+    let mut pg_client = pg_pool.get().await?;
+    let player_id = find_player_id(&pg_client, &player).await?;
+
+    let revoke_permission_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM player_permissions WHERE player_id = $1 AND permission = $2",
+            &[Type::INT4, Type::VARCHAR],
+        )
+        .await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let revoked = transaction
+        .execute(&revoke_permission_statement, &[&player_id, &permission])
+        .await?;
+
+    if revoked == 0 {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidPermission,
+            format!("Player {player} doesn't have the permission '{permission}'"),
+        ));
+    }
+
+    audit(
+        &transaction,
+        &req,
+        &config,
+        "revoke_permission",
+        Some(player_id),
+        serde_json::json!({ "permission": permission }),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Serialize)]
+struct RoleResponse {
+    name: String,
+    permissions: Vec<String>,
+}
+
+#[get("/admin/v1/roles")]
+async fn roles_get(
+    req: HttpRequest,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let pg_client = pg_pool.get().await?;
+
+    let get_roles = pg_client
+        .prepare_typed_cached(
+            "SELECT r.name, COALESCE(array_agg(rp.permission ORDER BY rp.permission) FILTER (WHERE rp.permission IS NOT NULL), '{}') FROM roles r LEFT JOIN role_permissions rp ON rp.role_id = r.id GROUP BY r.name ORDER BY r.name",
+            &[],
+        )
+        .await?;
+
+    let roles = pg_client
+        .query(&get_roles, &[])
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(RoleResponse {
+                name: row.try_get(0)?,
+                permissions: row.try_get(1)?,
+            })
+        })
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    Ok(HttpResponse::Ok().json(roles))
+}
+
+#[post("/admin/v1/roles/{role}/permissions")]
+async fn role_permission_grant(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Json<GrantPermissionParams>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let role = validate_role(&path)?;
+    let permission = validate_permission(&params.permission)?;
+
+    let mut pg_client = pg_pool.get().await?;
+
+    // roles are created on their first permission
+    let create_role_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO roles(name) VALUES($1) ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name RETURNING id",
+            &[Type::VARCHAR],
+        )
+        .await?;
+
+    let grant_permission_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO role_permissions(role_id, permission) VALUES($1, $2) ON CONFLICT (role_id, permission) DO NOTHING",
+            &[Type::INT4, Type::VARCHAR],
+        )
+        .await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let role_id: i32 = transaction
+        .query_one(&create_role_statement, &[&role])
+        .await?
+        .try_get(0)?;
+
+    let granted = transaction
+        .execute(&grant_permission_statement, &[&role_id, &permission])
+        .await?;
+
+    if granted > 0 {
+        audit(
+            &transaction,
+            &req,
+            &config,
+            "grant_role_permission",
+            None,
+            serde_json::json!({ "role": role, "permission": permission }),
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[delete("/admin/v1/roles/{role}/permissions/{permission}")]
+async fn role_permission_revoke(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let (role, permission) = path.into_inner();
+
+    let mut pg_client = pg_pool.get().await?;
+
+    let revoke_permission_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM role_permissions rp USING roles r WHERE r.id = rp.role_id AND r.name = $1 AND rp.permission = $2",
+            &[Type::VARCHAR, Type::VARCHAR],
+        )
+        .await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let revoked = transaction
+        .execute(&revoke_permission_statement, &[&role, &permission])
+        .await?;
+
+    if revoked == 0 {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidPermission,
+            format!("Role {role} doesn't have the permission '{permission}'"),
+        ));
+    }
+
+    audit(
+        &transaction,
+        &req,
+        &config,
+        "revoke_role_permission",
+        None,
+        serde_json::json!({ "role": role, "permission": permission }),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize)]
+struct AssignRoleParams {
+    role: String,
+}
+
+#[post("/admin/v1/players/{player}/roles")]
+async fn player_role_assign(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Json<AssignRoleParams>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let mut pg_client = pg_pool.get().await?;
+    let player_id = find_player_id(&pg_client, &path).await?;
+
+    let find_role_statement = pg_client
+        .prepare_typed_cached("SELECT id FROM roles WHERE name = $1", &[Type::VARCHAR])
+        .await?;
+
+    let assign_role_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO player_roles(player_id, role_id) VALUES($1, $2) ON CONFLICT (player_id, role_id) DO NOTHING",
+            &[Type::INT4, Type::INT4],
+        )
+        .await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let role_id: i32 = transaction
+        .query_opt(&find_role_statement, &[&params.role])
+        .await?
+        .ok_or(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidRole,
+            format!("Role '{}' doesn't exist", params.role),
+        ))?
+        .try_get(0)?;
+
+    let assigned = transaction
+        .execute(&assign_role_statement, &[&player_id, &role_id])
+        .await?;
+
+    if assigned > 0 {
+        audit(
+            &transaction,
+            &req,
+            &config,
+            "assign_role",
+            Some(player_id),
+            serde_json::json!({ "role": params.role }),
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[delete("/admin/v1/players/{player}/roles/{role}")]
+async fn player_role_unassign(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let (player, role) = path.into_inner();
+
+    let mut pg_client = pg_pool.get().await?;
+    let player_id = find_player_id(&pg_client, &player).await?;
+
+    let unassign_role_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM player_roles pr USING roles r WHERE r.id = pr.role_id AND pr.player_id = $1 AND r.name = $2",
+            &[Type::INT4, Type::VARCHAR],
+        )
+        .await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let unassigned = transaction
+        .execute(&unassign_role_statement, &[&player_id, &role])
+        .await?;
+
+    if unassigned == 0 {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidRole,
+            format!("Player {player} doesn't have the role '{role}'"),
+        ));
+    }
+
+    audit(
+        &transaction,
+        &req,
+        &config,
+        "unassign_role",
+        Some(player_id),
+        serde_json::json!({ "role": role }),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Serialize)]
+struct BanResponse {
+    id: i32,
+    reason: String,
+    issuer: String,
+    start_timestamp: i64,
+    end_timestamp: Option<i64>,
+}
+
+#[get("/admin/v1/players/{player}/bans")]
+async fn player_bans_get(
+    req: HttpRequest,
+    path: web::Path<String>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let pg_client = pg_pool.get().await?;
+    let player_id = find_player_id(&pg_client, &path).await?;
+
+    let get_player_bans = pg_client
+        .prepare_typed_cached(
+            "SELECT id, reason, issuer, EXTRACT(EPOCH FROM start_time)::int8, EXTRACT(EPOCH FROM end_time)::int8 FROM player_bans WHERE player_id = $1 ORDER BY start_time",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let bans = pg_client
+        .query(&get_player_bans, &[&player_id])
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(BanResponse {
+                id: row.try_get(0)?,
+                reason: row.try_get(1)?,
+                issuer: row.try_get(2)?,
+                start_timestamp: row.try_get(3)?,
+                end_timestamp: row.try_get(4)?,
+            })
+        })
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    Ok(HttpResponse::Ok().json(bans))
+}
+
+/// Longest ban which isn't permanent, about a century
+const MAX_BAN_DURATION: u64 = 100 * 365 * 24 * 60 * 60;
+
+#[derive(Deserialize)]
+struct BanParams {
+    reason: String,
+    issuer: String,
+    /// duration of the ban in seconds, the ban is permanent if unset
+    duration: Option<u64>,
+}
+
+#[post("/admin/v1/players/{player}/bans")]
+async fn player_ban(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Json<BanParams>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    if let Some(duration) = params.duration
+        && duration > MAX_BAN_DURATION
+    {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidBanDuration,
+            format!(
+                "Ban duration {duration} is above {MAX_BAN_DURATION}, omit it for a permanent ban"
+            ),
+        ));
+    }
+
+    let mut pg_client = pg_pool.get().await?;
+    let player_id = find_player_id(&pg_client, &path).await?;
+
+    let ban_player_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO player_bans(player_id, reason, issuer, start_time, end_time) VALUES($1, $2, $3, NOW(), NOW() + make_interval(secs => $4)) RETURNING id",
+            &[Type::INT4, Type::VARCHAR, Type::VARCHAR, Type::FLOAT8],
+        )
+        .await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let ban_id: i32 = transaction
+        .query_one(
+            &ban_player_statement,
+            &[
+                &player_id,
+                &params.reason,
+                &params.issuer,
+                &params.duration.map(|duration| duration as f64),
+            ],
+        )
+        .await?
+        .try_get(0)?;
+
+    audit(
+        &transaction,
+        &req,
+        &config,
+        "ban",
+        Some(player_id),
+        serde_json::json!({
+            "ban_id": ban_id,
+            "reason": params.reason,
+            "issuer": params.issuer,
+            "duration": params.duration,
+        }),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": ban_id })))
+}
+
+#[delete("/admin/v1/players/{player}/bans")]
+async fn player_unban(
+    req: HttpRequest,
+    path: web::Path<String>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let mut pg_client = pg_pool.get().await?;
+    let player_id = find_player_id(&pg_client, &path).await?;
+
+    // bans are ended instead of deleted to keep track of them
+    let unban_player_statement = pg_client
+        .prepare_typed_cached(
+            "UPDATE player_bans SET end_time = NOW() WHERE player_id = $1 AND start_time <= NOW() AND (end_time IS NULL OR end_time > NOW())",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let lifted = transaction
+        .execute(&unban_player_statement, &[&player_id])
+        .await?;
+
+    if lifted == 0 {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidId,
+            format!("Player {} isn't banned", path.as_str()),
+        ));
+    }
+
+    audit(
+        &transaction,
+        &req,
+        &config,
+        "unban",
+        Some(player_id),
+        serde_json::json!({ "lifted_bans": lifted }),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Serialize)]
+struct ChallengeResponse {
+    enabled: bool,
+    difficulty: u8,
+}
+
+#[get("/admin/v1/challenge")]
+async fn challenge_get(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    config: web::Data<ApiConfig>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    Ok(HttpResponse::Ok().json(ChallengeResponse {
+        enabled: config.player_creation_challenge_enabled,
+        difficulty: app_data.challenge_difficulty.load(Ordering::Relaxed),
+    }))
+}
+
+#[derive(Deserialize)]
+struct ChallengeParams {
+    difficulty: u8,
+}
+
+/// Changes the difficulty of the next player creation challenges, until the API restarts
+#[patch("/admin/v1/challenge")]
+async fn challenge_update(
+    req: HttpRequest,
+    params: web::Json<ChallengeParams>,
+    app_data: web::Data<AppData>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    if params.difficulty > MAX_DIFFICULTY {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidChallengeDifficulty,
+            format!(
+                "Challenge difficulty {} is above {MAX_DIFFICULTY}",
+                params.difficulty
+            ),
+        ));
+    }
+
+    let mut pg_client = pg_pool.get().await?;
+    let transaction = pg_client.transaction().await?;
+
+    let previous_difficulty = app_data.challenge_difficulty.load(Ordering::Relaxed);
+
+    audit(
+        &transaction,
+        &req,
+        &config,
+        "challenge_difficulty",
+        None,
+        serde_json::json!({
+            "previous_difficulty": previous_difficulty,
+            "difficulty": params.difficulty,
+        }),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    app_data
+        .challenge_difficulty
+        .store(params.difficulty, Ordering::Relaxed);
+
+    Ok(HttpResponse::Ok().json(ChallengeResponse {
+        enabled: config.player_creation_challenge_enabled,
+        difficulty: params.difficulty,
+    }))
+}
+
+#[derive(Serialize)]
+struct GameServerResponse {
+    id: i32,
+    name: String,
+    creation_timestamp: i64,
+    last_used_timestamp: Option<i64>,
+    /// set while the game server is running
+    registration: Option<GameServerRegistrationResponse>,
+}
+
+#[derive(Serialize)]
+struct GameServerRegistrationResponse {
+    address: String,
+    port: i32,
+    region: String,
+    capacity: i32,
+    version: String,
+    player_count: i32,
+    registration_timestamp: i64,
+    last_heartbeat_timestamp: i64,
+}
+
+#[get("/admin/v1/game_servers")]
+async fn game_servers_get(
+    req: HttpRequest,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let pg_client = pg_pool.get().await?;
+
+    let get_game_servers = pg_client
+        .prepare_typed_cached(
+            "SELECT s.id, s.name, EXTRACT(EPOCH FROM s.creation_time)::int8, EXTRACT(EPOCH FROM s.last_used_at)::int8, r.address, r.port, r.region, r.capacity, r.version, r.player_count, EXTRACT(EPOCH FROM r.registration_time)::int8, EXTRACT(EPOCH FROM r.last_heartbeat)::int8 FROM game_servers s LEFT JOIN game_server_registrations r ON r.game_server_id = s.id AND r.last_heartbeat > NOW() - make_interval(secs => $1) ORDER BY s.name",
+            &[Type::FLOAT8],
+        )
+        .await?;
+
+    let game_servers = pg_client
+        .query(
+            &get_game_servers,
+            &[&config.game_server_heartbeat_timeout.as_secs_f64()],
+        )
+        .await?
+        .into_iter()
+        .map(|row| {
+            let registration = match row.try_get::<_, Option<String>>(4)? {
+                Some(address) => Some(GameServerRegistrationResponse {
+                    address,
+                    port: row.try_get(5)?,
+                    region: row.try_get(6)?,
+                    capacity: row.try_get(7)?,
+                    version: row.try_get(8)?,
+                    player_count: row.try_get(9)?,
+                    registration_timestamp: row.try_get(10)?,
+                    last_heartbeat_timestamp: row.try_get(11)?,
+                }),
+                None => None,
+            };
+
+            Ok(GameServerResponse {
+                id: row.try_get(0)?,
+                name: row.try_get(1)?,
+                creation_timestamp: row.try_get(2)?,
+                last_used_timestamp: row.try_get(3)?,
+                registration,
+            })
+        })
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    Ok(HttpResponse::Ok().json(game_servers))
+}
+
+#[derive(Deserialize)]
+struct GameServerParams {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct GameServerCreatedResponse {
+    id: i32,
+    name: String,
+    key: Token,
+}
+
+/// Allows a game server to use the game server API, its key is only returned here
+#[post("/admin/v1/game_servers")]
+async fn game_server_create(
+    req: HttpRequest,
+    params: web::Json<GameServerParams>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let name = validate_game_server_name(&params.name)?;
+
+    let mut pg_client = pg_pool.get().await?;
+
+    let create_game_server_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO game_servers(name, key_hash, creation_time) VALUES($1, $2, NOW()) ON CONFLICT DO NOTHING RETURNING id",
+            &[Type::VARCHAR, Type::BYTEA],
+        )
+        .await?;
+
+    let Ok(key) = Token::generate(OsRng) else {
+        return Err(RouteError::ServerError(
+            ErrorCause::Internal,
+            ServerErrorCode::TokenGenerationFailed,
+        ));
+    };
+
+    let transaction = pg_client.transaction().await?;
+
+    let id: i32 = transaction
+        .query_opt(
+            &create_game_server_statement,
+            &[
+                &name,
+                &key.hash(config.game_server_key_pepper.unsecure().as_bytes()),
+            ],
+        )
+        .await?
+        .ok_or(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidGameServerName,
+            format!("Game server '{name}' already exists"),
+        ))?
+        .try_get(0)?;
+
+    audit(
+        &transaction,
+        &req,
+        &config,
+        "game_server_create",
+        None,
+        serde_json::json!({ "game_server_id": id, "name": name }),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(GameServerCreatedResponse {
+        id,
+        name: name.to_string(),
+        key,
+    }))
+}
+
+#[delete("/admin/v1/game_servers/{name}")]
+async fn game_server_delete(
+    req: HttpRequest,
+    path: web::Path<String>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    validate_admin(&req, &config)?;
+
+    let mut pg_client = pg_pool.get().await?;
+
+    let delete_game_server_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM game_servers WHERE name = $1 RETURNING id",
+            &[Type::VARCHAR],
+        )
+        .await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let id: i32 = transaction
+        .query_opt(&delete_game_server_statement, &[&path.as_str()])
+        .await?
+        .ok_or(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidGameServerName,
+            format!("Game server '{}' doesn't exist", path.as_str()),
+        ))?
+        .try_get(0)?;
+
+    audit(
+        &transaction,
+        &req,
+        &config,
+        "game_server_delete",
+        None,
+        serde_json::json!({ "game_server_id": id, "name": path.as_str() }),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn validate_permission(permission: &str) -> Result<&str, RouteError> {
+    let permission = permission.trim();
+
+    if !is_valid_name(permission) {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidPermission,
+            format!("Permission '{permission}' is invalid"),
+        ));
+    }
+
+    Ok(permission)
+}
+
+fn validate_role(role: &str) -> Result<&str, RouteError> {
+    let role = role.trim();
+
+    if !is_valid_name(role) {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidRole,
+            format!("Role '{role}' is invalid"),
+        ));
+    }
+
+    Ok(role)
+}
+
+fn validate_game_server_name(name: &str) -> Result<&str, RouteError> {
+    let name = name.trim();
+
+    if !is_valid_name(name) {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidGameServerName,
+            format!("Game server name '{name}' is invalid"),
+        ));
+    }
+
+    Ok(name)
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+}
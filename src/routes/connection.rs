@@ -1,18 +1,19 @@
 use actix_web::{HttpResponse, Responder, post, web};
 use deadpool_postgres::tokio_postgres::types::Type;
 use futures::{StreamExt, TryStreamExt};
-use jsonwebtoken::{EncodingKey, Header};
 use serde::Deserialize;
 use tokio_postgres::Row;
 use uuid::Uuid;
 
+use crate::app_data::AppData;
 use crate::config::ApiConfig;
 use crate::data::connection_token::{ConnectionToken, PrivateConnectionToken, ServerAddress};
-use crate::data::game_data_token::GameDataToken;
 use crate::data::player_data::PlayerData;
 use crate::errors::api::{ErrorCause, RouteError};
 use crate::errors::codes::ServerErrorCode;
+use crate::routes::game_server::insert_refresh_token;
 use crate::routes::players::validate_player_token;
+use crate::routes::registry::{SelectedGameServer, select_game_server};
 
 const DEV_TOKEN: &[u8] = const_base::decode!(
     "Unsecure+Developer+Token+Giving+Admin+Perms=",
@@ -23,19 +24,23 @@ const DEV_TOKEN: &[u8] = const_base::decode!(
 struct GameConnectionParams {
     token: String,
     dev: Option<bool>,
+    /// only the game servers of this region are considered
+    region: Option<String>,
+    /// connects to this game server instead of the least loaded one
+    server_id: Option<i32>,
 }
 
-#[post("/v1/game/connect")]
-async fn game_connect(
-    config: web::Data<ApiConfig>,
-    pg_pool: web::Data<deadpool_postgres::Pool>,
-    params: web::Json<GameConnectionParams>,
-) -> Result<impl Responder, RouteError> {
-    let pg_client = pg_pool.get().await?;
-    let player_id = validate_player_token(&pg_client, &params.token).await?;
-
-    let is_dev = params.dev.unwrap_or(false);
+/// Player sent to a game server by a connection token
+pub struct PlayerInfo {
+    pub uuid: Uuid,
+    pub nickname: String,
+    pub permissions: Vec<String>,
+}
 
+pub async fn find_player_info(
+    pg_client: &deadpool_postgres::Client,
+    player_id: i32,
+) -> Result<PlayerInfo, RouteError> {
     // TODO(SirLynix): to do this with only one query
     let find_player_info = pg_client
         .prepare_typed_cached(
@@ -52,41 +57,60 @@ async fn game_connect(
             format!("No player has the id '{player_id}'"),
         ))?;
 
-    let uuid: Uuid = player_result.try_get(0)?;
-    let nickname: String = player_result.try_get(1)?;
-
-    let permissions: Vec<String>;
-    if !is_dev {
-        let get_player_permissions = pg_client
-            .prepare_typed_cached(
-                "SELECT permission FROM player_permissions WHERE player_id = $1",
-                &[Type::INT4],
-            )
-            .await?;
-
-        permissions = pg_client
-            .query_raw(&get_player_permissions, &[&player_id])
-            .await?
-            .map(|row: Result<Row, tokio_postgres::Error>| row.and_then(|row| row.try_get(0)))
-            .try_collect()
-            .await?;
-    } else {
-        permissions = vec!["admin".into(), "dev".into()];
+    let get_player_permissions = pg_client
+        .prepare_typed_cached(
+            "SELECT permission FROM player_permissions WHERE player_id = $1 UNION SELECT rp.permission FROM player_roles pr JOIN role_permissions rp ON rp.role_id = pr.role_id WHERE pr.player_id = $1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let permissions: Vec<String> = pg_client
+        .query_raw(&get_player_permissions, &[&player_id])
+        .await?
+        .map(|row: Result<Row, tokio_postgres::Error>| row.and_then(|row| row.try_get(0)))
+        .try_collect()
+        .await?;
+
+    Ok(PlayerInfo {
+        uuid: player_result.try_get(0)?,
+        nickname: player_result.try_get(1)?,
+        permissions,
+    })
+}
+
+/// Generates the token allowing the player to connect to the game server, used by
+/// `/v1/game/connect` and the matchmaking
+pub async fn generate_connection_token<'s>(
+    pg_client: &deadpool_postgres::Client,
+    app_data: &AppData,
+    config: &ApiConfig,
+    player_id: i32,
+    player: PlayerInfo,
+    is_dev: bool,
+    game_server: &'s SelectedGameServer,
+) -> Result<ConnectionToken<'s>, RouteError> {
+    if let Some(game_server_id) = game_server.game_server_id {
+        log::info!(
+            "Sending player {} ({}) to game server {game_server_id}",
+            player.uuid,
+            player.nickname
+        );
     }
 
-    let player_data = PlayerData::new(uuid, nickname, permissions);
+    let uuid = player.uuid;
+    let player_data = PlayerData::new(player.uuid, player.nickname, player.permissions);
 
-    let refresh_token = GameDataToken::new_refresh(
+    // each connection starts a new refresh token family
+    let refresh_token_jwt = insert_refresh_token(
+        pg_client,
+        app_data,
+        config,
         player_id,
         uuid,
-        config.game_api_refresh_token_duration,
         is_dev,
-    );
-    let refresh_token_jwt = jsonwebtoken::encode(
-        &Header::default(),
-        &refresh_token,
-        &EncodingKey::from_secret(config.game_api_secret.unsecure().as_bytes()),
-    )?;
+        Uuid::new_v4(),
+    )
+    .await?;
 
     let private_token = PrivateConnectionToken::new(
         config.game_api_url.as_str(),
@@ -94,28 +118,92 @@ async fn game_connect(
         player_data,
     );
 
-    let server_address = if !is_dev {
-        ServerAddress::new(config.game_server_address.as_str(), config.game_server_port)
-    } else {
-        ServerAddress::new("localhost", config.game_server_port)
-    };
+    let server_address = ServerAddress::new(game_server.address.as_str(), game_server.port);
 
-    // force connection token key to be zero in dev mode to ensure it can't be used to connect to a regular server
-    let connection_token_key = chacha20poly1305::Key::from_slice(if !is_dev {
-        &config.connection_token_key
-    } else {
-        DEV_TOKEN
-    });
-
-    let token = ConnectionToken::generate(
-        connection_token_key,
+    ConnectionToken::generate(
+        &game_server.connection_token_key,
         config.connection_token_duration,
         server_address,
         private_token,
     )
     .map_err(|_| {
         RouteError::ServerError(ErrorCause::Internal, ServerErrorCode::TokenGenerationFailed)
-    })?;
+    })
+}
+
+#[post("/v1/game/connect")]
+async fn game_connect(
+    app_data: web::Data<AppData>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    params: web::Json<GameConnectionParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
+
+    let is_dev = params.dev.unwrap_or(false);
+
+    let mut player = find_player_info(&pg_client, player_id).await?;
+
+    if is_dev {
+        if !config.game_dev_mode_enabled {
+            return Err(RouteError::InvalidRequest(
+                ServerErrorCode::DevModeForbidden,
+                "Dev mode is disabled on this server".to_string(),
+            ));
+        }
+
+        if !player
+            .permissions
+            .iter()
+            .any(|permission| permission == "dev")
+        {
+            return Err(RouteError::InvalidRequest(
+                ServerErrorCode::DevModeForbidden,
+                format!(
+                    "Player {} ({}) doesn't have the dev permission",
+                    player.uuid, player.nickname
+                ),
+            ));
+        }
+
+        log::info!(
+            "Issuing a dev connection token to player {} ({})",
+            player.uuid,
+            player.nickname
+        );
+        player.permissions = vec!["admin".into(), "dev".into()];
+    }
+
+    // force connection token key to be the dev one in dev mode to ensure it can't be used to connect to a regular server
+    let game_server = if !is_dev {
+        select_game_server(
+            &pg_client,
+            &config,
+            params.region.as_deref(),
+            params.server_id,
+            1,
+        )
+        .await?
+    } else {
+        SelectedGameServer {
+            game_server_id: None,
+            address: config.game_dev_server_address.clone(),
+            port: config.game_server_port,
+            connection_token_key: *chacha20poly1305::Key::from_slice(DEV_TOKEN),
+        }
+    };
+
+    let token = generate_connection_token(
+        &pg_client,
+        &app_data,
+        &config,
+        player_id,
+        player,
+        is_dev,
+        &game_server,
+    )
+    .await?;
 
     Ok(HttpResponse::Ok().json(token))
 }
@@ -1,16 +1,59 @@
 use actix_web::{HttpRequest, HttpResponse, Responder, get, patch, post, web};
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode};
+use deadpool_postgres::GenericClient;
+use jsonwebtoken::Validation;
 use serde::{Deserialize, Serialize};
 use tokio_postgres::types::Type;
+use uuid::Uuid;
 
+use crate::app_data::AppData;
 use crate::config::ApiConfig;
 use crate::data::game_data_token::GameDataToken;
+use crate::data::token::hash_token;
 use crate::errors::api::RouteError;
 use crate::errors::codes::ServerErrorCode;
+use crate::routes::players::{find_active_ban, validate_player};
 
-fn validate_token(
+/// Header holding the key of a game server, given by `POST /admin/v1/game_servers`
+pub const GAME_SERVER_KEY_HEADER: &str = "X-Game-Server-Key";
+
+/// Checks the key of the game server doing the request and returns its id
+pub async fn validate_game_server(
     req: &HttpRequest,
+    pg_client: &deadpool_postgres::Client,
     config: &ApiConfig,
+) -> Result<i32, RouteError> {
+    let key = req
+        .headers()
+        .get(GAME_SERVER_KEY_HEADER)
+        .and_then(|header| header.to_str().ok())
+        .ok_or(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidGameServerCredentials,
+            "Missing game server credentials".to_string(),
+        ))?;
+
+    let use_game_server_statement = pg_client
+        .prepare_typed_cached(
+            "UPDATE game_servers SET last_used_at = NOW() WHERE key_hash = $1 RETURNING id",
+            &[Type::BYTEA],
+        )
+        .await?;
+
+    let key_hash = hash_token(config.game_server_key_pepper.unsecure().as_bytes(), key);
+
+    let game_server = pg_client
+        .query_opt(&use_game_server_statement, &[&key_hash])
+        .await?
+        .ok_or(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidGameServerCredentials,
+            "Invalid game server credentials".to_string(),
+        ))?;
+
+    Ok(game_server.try_get(0)?)
+}
+
+fn validate_token(
+    req: &HttpRequest,
+    app_data: &AppData,
     token_type: &str,
 ) -> Result<GameDataToken, RouteError> {
     let header = req
@@ -30,20 +73,15 @@ fn validate_token(
             RouteError::InvalidRequest(ServerErrorCode::InvalidToken(None), "Invalid token".into())
         })?;
 
-    let mut validation = Validation::new(jsonwebtoken::Algorithm::HS256);
-    validation.set_required_spec_claims(&["exp", "iat", "sub"]);
-
-    let token = decode::<GameDataToken>(
-        jwt,
-        &DecodingKey::from_secret(config.game_api_secret.unsecure().as_bytes()),
-        &validation,
-    )
-    .map_err(|err| {
-        RouteError::InvalidRequest(
-            ServerErrorCode::InvalidToken(Some(err.to_string())),
-            "Invalid token".to_string(),
-        )
-    })?;
+    let token = app_data
+        .game_api_keys
+        .decode::<GameDataToken>(jwt, token_validation())
+        .map_err(|err| {
+            RouteError::InvalidRequest(
+                ServerErrorCode::InvalidToken(Some(err.to_string())),
+                "Invalid token".to_string(),
+            )
+        })?;
 
     if token.claims.sub != token_type {
         log::error!(
@@ -59,6 +97,52 @@ fn validate_token(
     Ok(token.claims)
 }
 
+pub fn token_validation() -> Validation {
+    let mut validation = Validation::new(jsonwebtoken::Algorithm::HS256);
+    validation.set_required_spec_claims(&["exp", "iat", "sub"]);
+    validation
+}
+
+/// Issues a refresh token of `family_id` and stores it so it can only be used once
+pub async fn insert_refresh_token(
+    client: &impl GenericClient,
+    app_data: &AppData,
+    config: &ApiConfig,
+    player_id: i32,
+    player_uuid: Uuid,
+    is_readonly: bool,
+    family_id: Uuid,
+) -> Result<String, RouteError> {
+    let insert_refresh_token_statement = client
+        .prepare_typed_cached(
+            "INSERT INTO game_refresh_tokens(jti, family_id, player_id, expires_at) VALUES($1, $2, $3, NOW() + make_interval(secs => $4))",
+            &[Type::UUID, Type::UUID, Type::INT4, Type::FLOAT8],
+        )
+        .await?;
+
+    let refresh_token = GameDataToken::new_refresh(
+        player_id,
+        player_uuid,
+        config.game_api_refresh_token_duration,
+        is_readonly,
+        family_id,
+    );
+
+    client
+        .execute(
+            &insert_refresh_token_statement,
+            &[
+                &refresh_token.jti,
+                &family_id,
+                &player_id,
+                &config.game_api_refresh_token_duration.as_secs_f64(),
+            ],
+        )
+        .await?;
+
+    Ok(app_data.game_api_keys.encode(&refresh_token)?)
+}
+
 #[derive(Serialize)]
 struct RefreshTokenResponse {
     access_token: String,
@@ -66,36 +150,159 @@ struct RefreshTokenResponse {
     refresh_token: String,
 }
 
+#[get("/.well-known/jwks.json")]
+async fn jwks(app_data: web::Data<AppData>) -> impl Responder {
+    HttpResponse::Ok().json(app_data.game_api_keys.jwks())
+}
+
+/// Exchanges a refresh token for a new access token and a new refresh token
+///
+/// A refresh token can only be used once, using it again means it leaked so every token of its
+/// family is revoked.
 #[post("/game_server/v1/refresh")]
 async fn refresh_access_token(
     req: HttpRequest,
+    app_data: web::Data<AppData>,
     config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
 ) -> Result<impl Responder, RouteError> {
-    let refresh_token = validate_token(&req, &config, "refresh")?;
+    let mut pg_client = pg_pool.get().await?;
+    validate_game_server(&req, &pg_client, &config).await?;
 
-    let access_token = GameDataToken::new_access(
+    let refresh_token = validate_token(&req, &app_data, "refresh")?;
+    // refresh tokens issued before the rotation have no id, each is accepted once and starts a family
+    let (jti, legacy) = match refresh_token.jti {
+        Some(jti) => (jti, false),
+        None => (refresh_token.legacy_jti(), true),
+    };
+
+    let rotate_refresh_token_statement = pg_client
+        .prepare_typed_cached(
+            "UPDATE game_refresh_tokens SET rotated_at = NOW() WHERE jti = $1 AND rotated_at IS NULL AND expires_at > NOW() RETURNING family_id",
+            &[Type::UUID],
+        )
+        .await?;
+
+    let find_refresh_token_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT family_id FROM game_refresh_tokens WHERE jti = $1 AND rotated_at IS NOT NULL",
+            &[Type::UUID],
+        )
+        .await?;
+
+    let insert_legacy_token_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO game_refresh_tokens(jti, family_id, player_id, expires_at, rotated_at) VALUES($1, $2, $3, NOW() + make_interval(secs => $4), NOW()) ON CONFLICT (jti) DO NOTHING",
+            &[Type::UUID, Type::UUID, Type::INT4, Type::FLOAT8],
+        )
+        .await?;
+
+    let revoke_family_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM game_refresh_tokens WHERE family_id = $1",
+            &[Type::UUID],
+        )
+        .await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let family_id: Uuid = match transaction
+        .query_opt(&rotate_refresh_token_statement, &[&jti])
+        .await?
+    {
+        Some(row) => row.try_get(0)?,
+        None => {
+            let rotated_token = transaction
+                .query_opt(&find_refresh_token_statement, &[&jti])
+                .await?;
+
+            match rotated_token {
+                Some(rotated_token) => {
+                    let family_id: Uuid = rotated_token.try_get(0)?;
+                    let revoked = transaction
+                        .execute(&revoke_family_statement, &[&family_id])
+                        .await?;
+
+                    transaction.commit().await?;
+
+                    log::warn!(
+                        "Refresh token {jti} of player {} was reused, {revoked} tokens of family {family_id} revoked",
+                        refresh_token.player_uuid
+                    );
+
+                    return Err(RouteError::InvalidRequest(
+                        ServerErrorCode::InvalidToken(Some(format!(
+                            "Refresh token {jti} was already used"
+                        ))),
+                        "Invalid token".to_string(),
+                    ));
+                }
+                None if legacy => {
+                    // the token is kept as rotated so that it can't be used again
+                    let family_id = Uuid::new_v4();
+                    let expires_in = refresh_token
+                        .exp
+                        .saturating_sub(jsonwebtoken::get_current_timestamp());
+
+                    let inserted = transaction
+                        .execute(
+                            &insert_legacy_token_statement,
+                            &[
+                                &jti,
+                                &family_id,
+                                &refresh_token.player_db_id,
+                                &(expires_in as f64),
+                            ],
+                        )
+                        .await?;
+
+                    if inserted == 0 {
+                        return Err(RouteError::InvalidRequest(
+                            ServerErrorCode::InvalidToken(Some(format!(
+                                "Refresh token {jti} was already used"
+                            ))),
+                            "Invalid token".to_string(),
+                        ));
+                    }
+
+                    family_id
+                }
+                None => {
+                    return Err(RouteError::InvalidRequest(
+                        ServerErrorCode::InvalidToken(Some(format!(
+                            "Refresh token {jti} is unknown or revoked"
+                        ))),
+                        "Invalid token".to_string(),
+                    ));
+                }
+            }
+        }
+    };
+
+    // a ban or a deletion request ends the sessions on the game servers at their next refresh
+    validate_player(&transaction, refresh_token.player_db_id).await?;
+
+    let refresh_token_jwt = insert_refresh_token(
+        &transaction,
+        &app_data,
+        &config,
         refresh_token.player_db_id,
         refresh_token.player_uuid,
-        config.game_api_access_token_duration,
         refresh_token.is_readonly,
-    );
-    let refresh_token = GameDataToken::new_refresh(
+        family_id,
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    let access_token = GameDataToken::new_access(
         refresh_token.player_db_id,
         refresh_token.player_uuid,
-        config.game_api_refresh_token_duration,
+        config.game_api_access_token_duration,
         refresh_token.is_readonly,
+        family_id,
     );
-
-    let access_token_jwt = jsonwebtoken::encode(
-        &Header::default(),
-        &access_token,
-        &EncodingKey::from_secret(config.game_api_secret.unsecure().as_bytes()),
-    )?;
-    let refresh_token_jwt = jsonwebtoken::encode(
-        &Header::default(),
-        &refresh_token,
-        &EncodingKey::from_secret(config.game_api_secret.unsecure().as_bytes()),
-    )?;
+    let access_token_jwt = app_data.game_api_keys.encode(&access_token)?;
 
     Ok(HttpResponse::Ok().json(RefreshTokenResponse {
         access_token: access_token_jwt,
@@ -104,6 +311,137 @@ async fn refresh_access_token(
     }))
 }
 
+#[derive(Deserialize)]
+struct IntrospectParams {
+    token: String,
+}
+
+#[derive(Serialize)]
+struct IntrospectResponse {
+    active: bool,
+    #[serde(flatten)]
+    token: Option<IntrospectedToken>,
+}
+
+#[derive(Serialize)]
+struct IntrospectedToken {
+    token_type: String,
+    player_uuid: Uuid,
+    readonly: bool,
+    exp: u64,
+    iat: u64,
+    /// the token was rotated or revoked, or its player doesn't exist anymore
+    revoked: bool,
+    pending_deletion: bool,
+    ban: Option<IntrospectedBan>,
+}
+
+#[derive(Serialize)]
+struct IntrospectedBan {
+    reason: String,
+    expire_timestamp: Option<i64>,
+}
+
+/// Tells a game server whether a game API token is still active (RFC 7662)
+///
+/// Only `active` is returned for a token which couldn't be verified (bad signature, expired),
+/// otherwise the state of the token and its player is returned as well.
+/// An access token is revoked with its refresh token family, except the access tokens issued
+/// before they carried their family which are only revoked with their player.
+#[post("/game_server/v1/introspect")]
+async fn introspect(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    params: web::Json<IntrospectParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    validate_game_server(&req, &pg_client, &config).await?;
+
+    let Ok(token) = app_data
+        .game_api_keys
+        .decode::<GameDataToken>(&params.token, token_validation())
+    else {
+        return Ok(HttpResponse::Ok().json(IntrospectResponse {
+            active: false,
+            token: None,
+        }));
+    };
+    let claims = token.claims;
+
+    let find_player_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT deletion_time IS NOT NULL FROM players WHERE id = $1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let find_refresh_token_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT rotated_at IS NULL FROM game_refresh_tokens WHERE jti = $1",
+            &[Type::UUID],
+        )
+        .await?;
+
+    let find_family_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT EXISTS(SELECT 1 FROM game_refresh_tokens WHERE family_id = $1)",
+            &[Type::UUID],
+        )
+        .await?;
+
+    let pending_deletion: Option<bool> = pg_client
+        .query_opt(&find_player_statement, &[&claims.player_db_id])
+        .await?
+        .map(|row| row.try_get(0))
+        .transpose()?;
+
+    let mut revoked = pending_deletion.is_none();
+    if claims.sub == "refresh" {
+        let jti = claims.jti.unwrap_or_else(|| claims.legacy_jti());
+        let usable: Option<bool> = pg_client
+            .query_opt(&find_refresh_token_statement, &[&jti])
+            .await?
+            .map(|row| row.try_get(0))
+            .transpose()?;
+
+        // a refresh token without id is only stored once it is used
+        revoked |= !usable.unwrap_or(claims.jti.is_none());
+    } else if let Some(family_id) = claims.family_id {
+        // the family is deleted when it is revoked, its refresh tokens outlive its access tokens
+        let family_exists: bool = pg_client
+            .query_one(&find_family_statement, &[&family_id])
+            .await?
+            .try_get(0)?;
+
+        revoked |= !family_exists;
+    }
+
+    let ban = find_active_ban(&pg_client, claims.player_db_id)
+        .await?
+        .map(|ban| IntrospectedBan {
+            reason: ban.reason,
+            expire_timestamp: ban.expire_timestamp,
+        });
+
+    let pending_deletion = pending_deletion.unwrap_or(false);
+
+    Ok(HttpResponse::Ok().json(IntrospectResponse {
+        active: !revoked && !pending_deletion && ban.is_none(),
+        token: Some(IntrospectedToken {
+            token_type: claims.sub,
+            player_uuid: claims.player_uuid,
+            readonly: claims.is_readonly,
+            exp: claims.exp,
+            iat: claims.iat,
+            revoked,
+            pending_deletion,
+            ban,
+        }),
+    }))
+}
+
 #[derive(Serialize)]
 struct GetShipResponse {
     ship_data: serde_json::Value,
@@ -112,13 +450,16 @@ struct GetShipResponse {
 #[get("/game_server/v1/player_ship/{ship_slot}")]
 async fn player_ship_get(
     req: HttpRequest,
+    app_data: web::Data<AppData>,
     config: web::Data<ApiConfig>,
     path: web::Path<i32>,
     pg_pool: web::Data<deadpool_postgres::Pool>,
 ) -> Result<impl Responder, RouteError> {
-    let access_token = validate_token(&req, &config, "access")?;
-
     let pg_client = pg_pool.get().await?;
+    validate_game_server(&req, &pg_client, &config).await?;
+
+    let access_token = validate_token(&req, &app_data, "access")?;
+
     let get_player_ship = pg_client
         .prepare_typed_cached(
             "SELECT data FROM player_ships WHERE player_id = $1 AND slot = $2",
@@ -143,15 +484,20 @@ struct ShipPatchParams {
     data: serde_json::Value,
 }
 
+/// Writes a ship of the player, requires both the player access token and the game server key
 #[patch("/game_server/v1/player_ship/{ship_slot}")]
 async fn player_ship_patch(
     req: HttpRequest,
     path: web::Path<i32>,
     params: web::Json<ShipPatchParams>,
+    app_data: web::Data<AppData>,
     config: web::Data<ApiConfig>,
     pg_pool: web::Data<deadpool_postgres::Pool>,
 ) -> Result<impl Responder, RouteError> {
-    let access_token = validate_token(&req, &config, "access")?;
+    let pg_client = pg_pool.get().await?;
+    let game_server_id = validate_game_server(&req, &pg_client, &config).await?;
+
+    let access_token = validate_token(&req, &app_data, "access")?;
     if access_token.is_readonly {
         return Err(RouteError::InvalidRequest(
             ServerErrorCode::InvalidToken(Some("Token is readonly".into())),
@@ -159,18 +505,22 @@ async fn player_ship_patch(
         ));
     }
 
-    let pg_client = pg_pool.get().await?;
     let insert_player_ship = pg_client
         .prepare_typed_cached(
-            "INSERT INTO player_ships(player_id, slot, last_update, data) VALUES($1, $2, NOW(), $3) ON CONFLICT(player_id, slot) DO UPDATE SET last_update = NOW(), data = EXCLUDED.data",
-            &[Type::INT4, Type::INT4, Type::JSONB],
+            "INSERT INTO player_ships(player_id, slot, last_update, data, last_update_game_server_id) VALUES($1, $2, NOW(), $3, $4) ON CONFLICT(player_id, slot) DO UPDATE SET last_update = NOW(), data = EXCLUDED.data, last_update_game_server_id = EXCLUDED.last_update_game_server_id",
+            &[Type::INT4, Type::INT4, Type::JSONB, Type::INT4],
         )
         .await?;
 
     pg_client
         .execute(
             &insert_player_ship,
-            &[&access_token.player_db_id, &*path, &params.data],
+            &[
+                &access_token.player_db_id,
+                &*path,
+                &params.data,
+                &game_server_id,
+            ],
         )
         .await?;
 
@@ -0,0 +1,155 @@
+use actix_web::{HttpResponse, Responder, post, web};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::app_data::AppData;
+use crate::config::ApiConfig;
+use crate::data::connection_token::ConnectionToken;
+use crate::errors::api::RouteError;
+use crate::errors::codes::ServerErrorCode;
+use crate::matchmaking::{Matchmaker, TicketState};
+use crate::routes::connection::{find_player_info, generate_connection_token};
+use crate::routes::players::validate_player_token;
+
+#[derive(Deserialize)]
+struct EnqueueParams {
+    token: String,
+    mode: String,
+    /// only players of the same region are matched together, on a game server of this region
+    region: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EnqueueResponse {
+    ticket: Uuid,
+    /// seconds after which the ticket is dropped if it isn't polled
+    ticket_timeout: u64,
+}
+
+/// Queues the player for a match, the match is then retrieved with `/v1/matchmaking/poll`
+#[post("/v1/matchmaking/enqueue")]
+async fn enqueue(
+    matchmaker: web::Data<Matchmaker>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    params: web::Json<EnqueueParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
+
+    if !matchmaker.has_mode(&params.mode) {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::UnknownMatchmakingMode,
+            format!("Matchmaking mode '{}' doesn't exist", params.mode),
+        ));
+    }
+
+    let ticket = matchmaker.enqueue(player_id, &params.mode, params.region.as_deref());
+
+    // forms the match right away if this player completes it
+    matchmaker.form_matches(&pg_client, &config).await;
+
+    Ok(HttpResponse::Ok().json(EnqueueResponse {
+        ticket,
+        ticket_timeout: config.matchmaking_ticket_timeout.as_secs(),
+    }))
+}
+
+#[derive(Deserialize)]
+struct PollParams {
+    token: String,
+    ticket: Uuid,
+    /// waits up to `matchmaking_long_poll_duration` for the match instead of answering right away
+    #[serde(default)]
+    wait: bool,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum PollResponse<'s> {
+    Queued,
+    Matched {
+        connection_token: ConnectionToken<'s>,
+    },
+}
+
+/// Keeps the player in the queue and gives its connection token once a match is found
+#[post("/v1/matchmaking/poll")]
+async fn poll(
+    app_data: web::Data<AppData>,
+    matchmaker: web::Data<Matchmaker>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    params: web::Json<PollParams>,
+) -> Result<impl Responder, RouteError> {
+    // the database client isn't kept while waiting for the match
+    let player_id = {
+        let pg_client = pg_pool.get().await?;
+        validate_player_token(&pg_client, &config, &params.token).await?
+    };
+
+    let mut ticket_state =
+        matchmaker
+            .poll(params.ticket, player_id)
+            .ok_or(RouteError::InvalidRequest(
+                ServerErrorCode::InvalidMatchmakingTicket,
+                format!("Unknown matchmaking ticket {}", params.ticket),
+            ))?;
+
+    if params.wait {
+        let _ = tokio::time::timeout(
+            config.matchmaking_long_poll_duration,
+            ticket_state.wait_for(|state| matches!(state, TicketState::Matched(_))),
+        )
+        .await;
+    }
+
+    let TicketState::Matched(game_server) = ticket_state.borrow().clone() else {
+        return Ok(HttpResponse::Ok().json(PollResponse::Queued));
+    };
+
+    let pg_client = pg_pool.get().await?;
+    let player = find_player_info(&pg_client, player_id).await?;
+
+    let connection_token = generate_connection_token(
+        &pg_client,
+        &app_data,
+        &config,
+        player_id,
+        player,
+        false,
+        &game_server,
+    )
+    .await?;
+
+    // the ticket is kept until the token is generated so the player can retry on failure
+    matchmaker.remove(params.ticket, player_id);
+
+    Ok(HttpResponse::Ok().json(PollResponse::Matched { connection_token }))
+}
+
+#[derive(Deserialize)]
+struct CancelParams {
+    token: String,
+    ticket: Uuid,
+}
+
+#[post("/v1/matchmaking/cancel")]
+async fn cancel(
+    matchmaker: web::Data<Matchmaker>,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    params: web::Json<CancelParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
+
+    if !matchmaker.remove(params.ticket, player_id) {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidMatchmakingTicket,
+            format!("Unknown matchmaking ticket {}", params.ticket),
+        ));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -1,4 +1,11 @@
+pub mod account;
+pub mod admin;
 pub mod connection;
 pub mod game_server;
+pub mod matchmaking;
+pub mod oidc;
 pub mod players;
+pub mod recovery;
+pub mod registry;
+pub mod tokens;
 pub mod version;
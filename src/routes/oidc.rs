@@ -0,0 +1,346 @@
+use actix_web::{HttpRequest, HttpResponse, Responder, post, web};
+use deadpool_postgres::tokio_postgres::types::Type;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::app_data::AppData;
+use crate::config::ApiConfig;
+use crate::data::token::Token;
+use crate::errors::api::{ErrorCause, RouteError};
+use crate::errors::codes::ServerErrorCode;
+use crate::nickname::Nickname;
+use crate::oidc::{OidcClient, OidcLogin};
+use crate::routes::players::{
+    insert_player, insert_player_token, reserve_player_creation, use_challenge, validate_challenge,
+    validate_creation_address, validate_player, validate_player_token,
+};
+
+fn oidc_client(app_data: &AppData) -> Result<&OidcClient, RouteError> {
+    app_data.oidc.as_ref().ok_or(RouteError::InvalidRequest(
+        ServerErrorCode::OidcDisabled,
+        "OIDC login is disabled".to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct AuthorizeParams {
+    /// links the identity to this player instead of logging in
+    token: Option<String>,
+    /// nickname of the player created if the identity isn't linked yet
+    nickname: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AuthorizeResponse {
+    authorization_url: String,
+    state: String,
+}
+
+#[post("/v1/oidc/authorize")]
+async fn authorize(
+    app_data: web::Data<AppData>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<AuthorizeParams>,
+) -> Result<impl Responder, RouteError> {
+    let oidc_client = oidc_client(&app_data)?;
+
+    let pg_client = pg_pool.get().await?;
+    let player_id = match &params.token {
+        Some(token) => Some(validate_player_token(&pg_client, &config, token).await?),
+        None => None,
+    };
+
+    let Ok(login) = OidcLogin::generate(OsRng) else {
+        return Err(RouteError::ServerError(
+            ErrorCause::Internal,
+            ServerErrorCode::TokenGenerationFailed,
+        ));
+    };
+
+    let authorization_url = oidc_client.authorization_url(&login).await?;
+
+    let insert_login_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO oidc_logins(state, nonce, code_verifier, player_id, nickname, expires_at) VALUES($1, $2, $3, $4, $5, NOW() + make_interval(secs => $6))",
+            &[
+                Type::VARCHAR,
+                Type::VARCHAR,
+                Type::VARCHAR,
+                Type::INT4,
+                Type::VARCHAR,
+                Type::FLOAT8,
+            ],
+        )
+        .await?;
+
+    pg_client
+        .execute(
+            &insert_login_statement,
+            &[
+                &login.state,
+                &login.nonce,
+                &login.code_verifier,
+                &player_id,
+                &params.nickname,
+                &config.oidc_login_duration.as_secs_f64(),
+            ],
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(AuthorizeResponse {
+        authorization_url,
+        state: login.state,
+    }))
+}
+
+/// What finishing a login does with the identity
+#[derive(Debug, PartialEq)]
+enum LoginOutcome {
+    /// the identity is already linked to this player
+    Linked(i32, Uuid),
+    /// the identity is linked to this player, who started the login
+    Link(i32),
+    /// a player is created for the identity
+    Create,
+}
+
+fn login_outcome(
+    linked_player: Option<(i32, Uuid)>,
+    link_player_id: Option<i32>,
+    subject: &str,
+) -> Result<LoginOutcome, RouteError> {
+    match (linked_player, link_player_id) {
+        (Some((player_id, uuid)), None) => Ok(LoginOutcome::Linked(player_id, uuid)),
+        (Some((player_id, uuid)), Some(link_player_id)) if player_id == link_player_id => {
+            Ok(LoginOutcome::Linked(player_id, uuid))
+        }
+        (Some(_), Some(link_player_id)) => Err(RouteError::InvalidRequest(
+            ServerErrorCode::IdentityAlreadyLinked,
+            format!("Identity {subject} is already linked to another player than {link_player_id}"),
+        )),
+        (None, Some(link_player_id)) => Ok(LoginOutcome::Link(link_player_id)),
+        (None, None) => Ok(LoginOutcome::Create),
+    }
+}
+
+#[derive(Deserialize)]
+struct OidcTokenParams {
+    state: String,
+    code: String,
+    /// solved player creation challenge, only checked if a player is created
+    challenge: Option<String>,
+    solution: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OidcTokenResponse {
+    uuid: Uuid,
+    token: Token,
+    created: bool,
+}
+
+/// Finishes an OIDC login, giving a token to the player linked to the identity unless they are
+/// banned or pending deletion
+///
+/// The player is created if the identity isn't linked to anyone, with the same address checks,
+/// limits and challenge as `POST /v1/players`.
+#[post("/v1/oidc/token")]
+async fn finish_login(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<OidcTokenParams>,
+) -> Result<impl Responder, RouteError> {
+    let oidc_client = oidc_client(&app_data)?;
+
+    let mut pg_client = pg_pool.get().await?;
+
+    // a login can only be finished once
+    let use_login_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM oidc_logins WHERE state = $1 RETURNING nonce, code_verifier, player_id, nickname, expires_at > NOW()",
+            &[Type::VARCHAR],
+        )
+        .await?;
+
+    let find_identity_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT i.player_id, p.uuid FROM player_identities i JOIN players p ON p.id = i.player_id WHERE i.issuer = $1 AND i.subject = $2",
+            &[Type::VARCHAR, Type::VARCHAR],
+        )
+        .await?;
+
+    let find_player_uuid_statement = pg_client
+        .prepare_typed_cached("SELECT uuid FROM players WHERE id = $1", &[Type::INT4])
+        .await?;
+
+    let insert_identity_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO player_identities(player_id, issuer, subject, creation_time) VALUES($1, $2, $3, NOW())",
+            &[Type::INT4, Type::VARCHAR, Type::VARCHAR],
+        )
+        .await?;
+
+    let update_identity_statement = pg_client
+        .prepare_typed_cached(
+            "UPDATE player_identities SET last_used_at = NOW() WHERE issuer = $1 AND subject = $2",
+            &[Type::VARCHAR, Type::VARCHAR],
+        )
+        .await?;
+
+    let login_result = pg_client
+        .query_opt(&use_login_statement, &[&params.state])
+        .await?
+        .ok_or(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidOidcState,
+            "Unknown or already used OIDC state".to_string(),
+        ))?;
+
+    if !login_result.try_get::<_, bool>(4)? {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidOidcState,
+            "OIDC login has expired".to_string(),
+        ));
+    }
+
+    let login = OidcLogin {
+        state: params.state.clone(),
+        nonce: login_result.try_get(0)?,
+        code_verifier: login_result.try_get(1)?,
+    };
+    let link_player_id: Option<i32> = login_result.try_get(2)?;
+    let nickname: Option<String> = login_result.try_get(3)?;
+
+    let identity = oidc_client.exchange_code(&params.code, &login).await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let linked_player = transaction
+        .query_opt(
+            &find_identity_statement,
+            &[&identity.issuer, &identity.subject],
+        )
+        .await?
+        .map(|row| Ok::<_, tokio_postgres::Error>((row.try_get::<_, i32>(0)?, row.try_get(1)?)))
+        .transpose()?;
+
+    let (player_id, uuid, created) =
+        match login_outcome(linked_player, link_player_id, &identity.subject)? {
+            LoginOutcome::Linked(player_id, uuid) => {
+                validate_player(&transaction, player_id).await?;
+
+                (player_id, uuid, false)
+            }
+            LoginOutcome::Link(link_player_id) => {
+                transaction
+                    .execute(
+                        &insert_identity_statement,
+                        &[&link_player_id, &identity.issuer, &identity.subject],
+                    )
+                    .await?;
+
+                let uuid: Uuid = transaction
+                    .query_one(&find_player_uuid_statement, &[&link_player_id])
+                    .await?
+                    .try_get(0)?;
+
+                log::info!(
+                    "Player {link_player_id} linked the identity {}",
+                    identity.subject
+                );
+
+                (link_player_id, uuid, false)
+            }
+            LoginOutcome::Create => {
+                let quota_ip = validate_creation_address(&req, &config)?;
+
+                let challenge = validate_challenge(
+                    &config,
+                    params.challenge.as_deref(),
+                    params.solution.as_deref(),
+                )?;
+
+                let nickname = Nickname::validate(
+                    &config,
+                    &app_data.nickname_blocklist,
+                    nickname
+                        .as_deref()
+                        .or(identity.preferred_username.as_deref())
+                        .unwrap_or_default(),
+                )?;
+
+                if let Some(challenge) = &challenge {
+                    use_challenge(&transaction, challenge).await?;
+                }
+
+                reserve_player_creation(&transaction, &config, quota_ip).await?;
+
+                let (player_id, uuid) = insert_player(&transaction, &nickname).await?;
+
+                transaction
+                    .execute(
+                        &insert_identity_statement,
+                        &[&player_id, &identity.issuer, &identity.subject],
+                    )
+                    .await?;
+
+                (player_id, uuid, true)
+            }
+        };
+
+    transaction
+        .execute(
+            &update_identity_statement,
+            &[&identity.issuer, &identity.subject],
+        )
+        .await?;
+
+    let token = insert_player_token(&transaction, &config, player_id).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(OidcTokenResponse {
+        uuid,
+        token,
+        created,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_outcome_links_or_creates() {
+        let uuid = Uuid::new_v4();
+
+        assert_eq!(
+            login_outcome(Some((1, uuid)), None, "sub").unwrap(),
+            LoginOutcome::Linked(1, uuid)
+        );
+        assert_eq!(
+            login_outcome(Some((1, uuid)), Some(1), "sub").unwrap(),
+            LoginOutcome::Linked(1, uuid)
+        );
+        assert_eq!(
+            login_outcome(None, Some(2), "sub").unwrap(),
+            LoginOutcome::Link(2)
+        );
+        assert_eq!(
+            login_outcome(None, None, "sub").unwrap(),
+            LoginOutcome::Create
+        );
+    }
+
+    #[test]
+    fn login_outcome_refuses_identities_of_other_players() {
+        let Err(RouteError::InvalidRequest(ServerErrorCode::IdentityAlreadyLinked, _)) =
+            login_outcome(Some((1, Uuid::new_v4())), Some(2), "sub")
+        else {
+            panic!("an identity linked to player 1 was linked to player 2");
+        };
+    }
+}
@@ -1,79 +1,385 @@
-use actix_web::{HttpResponse, Responder, post, web};
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+
+use actix_web::{HttpRequest, HttpResponse, Responder, get, post, web};
+use deadpool_postgres::GenericClient;
+use deadpool_postgres::tokio_postgres::error::SqlState;
 use deadpool_postgres::tokio_postgres::types::Type;
 
 use rand_core::OsRng;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::app_data::AppData;
+use crate::client_ip::{client_ip, is_denied, quota_key};
 use crate::config::ApiConfig;
-use crate::data::token::Token;
+use crate::data::challenge::Challenge;
+use crate::data::token::{Token, hash_token};
 use crate::errors::api::ErrorCause;
 use crate::errors::api::RouteError;
-use crate::errors::codes::ServerErrorCode;
+use crate::errors::codes::{ServerErrorCode, token_suffix};
+use crate::nickname::Nickname;
+use crate::routes::recovery::generate_recovery_codes;
 
 #[derive(Deserialize)]
 struct CreatePlayerParams {
     nickname: String,
+    challenge: Option<String>,
+    solution: Option<String>,
+    #[serde(default)]
+    recovery_codes: bool,
 }
 
 #[derive(Serialize)]
 struct CreatePlayerResponse {
     uuid: Uuid,
     token: Token,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recovery_codes: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct ChallengeResponse {
+    challenge: String,
+    difficulty: u8,
+    expire_timestamp: u64,
+}
+
+#[get("/v1/players/challenge")]
+async fn creation_challenge(
+    app_data: web::Data<AppData>,
+    config: web::Data<ApiConfig>,
+) -> Result<impl Responder, RouteError> {
+    if !config.player_creation_challenge_enabled {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::ChallengeDisabled,
+            "Player creation challenge is disabled".to_string(),
+        ));
+    }
+
+    let Ok(challenge) = Challenge::generate(
+        OsRng,
+        config.player_creation_challenge_duration,
+        app_data.challenge_difficulty.load(Ordering::Relaxed),
+    ) else {
+        return Err(RouteError::ServerError(
+            ErrorCause::Internal,
+            ServerErrorCode::TokenGenerationFailed,
+        ));
+    };
+
+    Ok(HttpResponse::Ok().json(ChallengeResponse {
+        challenge: challenge.encode(
+            config
+                .player_creation_challenge_secret
+                .unsecure()
+                .as_bytes(),
+        ),
+        difficulty: challenge.difficulty,
+        expire_timestamp: challenge.expire_timestamp,
+    }))
+}
+
+/// Checks that players can be created from the address of the request, returns the address
+/// counted by the player creation limits
+pub fn validate_creation_address(
+    req: &HttpRequest,
+    config: &ApiConfig,
+) -> Result<IpAddr, RouteError> {
+    let Some(ip) = client_ip(req.peer_addr(), req.headers(), &config.trusted_proxies) else {
+        return Err(RouteError::ServerError(
+            ErrorCause::Internal,
+            ServerErrorCode::External("Could not extract the client IP address".to_string()),
+        ));
+    };
+
+    if is_denied(&ip, &config.player_creation_ip_denylist) {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::IpDenied(ip),
+            "Player creation is not allowed from this address".to_string(),
+        ));
+    }
+
+    Ok(quota_key(ip))
+}
+
+/// Checks the solution of the player creation challenge if required
+pub fn validate_challenge(
+    config: &ApiConfig,
+    encoded_challenge: Option<&str>,
+    solution: Option<&str>,
+) -> Result<Option<Challenge>, RouteError> {
+    if !config.player_creation_challenge_enabled {
+        return Ok(None);
+    }
+
+    let (Some(encoded_challenge), Some(solution)) = (encoded_challenge, solution) else {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::ChallengeRequired,
+            "Missing challenge or solution".to_string(),
+        ));
+    };
+
+    let challenge = Challenge::decode(
+        config
+            .player_creation_challenge_secret
+            .unsecure()
+            .as_bytes(),
+        encoded_challenge,
+    )
+    .ok_or(RouteError::InvalidRequest(
+        ServerErrorCode::InvalidChallenge,
+        "Challenge signature is invalid".to_string(),
+    ))?;
+
+    let expired = challenge
+        .is_expired()
+        .map_err(|err| RouteError::ServerError(ErrorCause::Internal, ServerErrorCode::from(err)))?;
+
+    if expired {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidChallenge,
+            "Challenge has expired".to_string(),
+        ));
+    }
+
+    if !challenge.is_solved_by(encoded_challenge, solution) {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidChallenge,
+            format!("Wrong solution '{solution}' for the challenge"),
+        ));
+    }
+
+    Ok(Some(challenge))
 }
 
 #[post("/v1/players")]
 async fn create(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
     pg_pool: web::Data<deadpool_postgres::Pool>,
     config: web::Data<ApiConfig>,
     params: web::Json<CreatePlayerParams>,
 ) -> Result<impl Responder, RouteError> {
-    let nickname = params.nickname.trim();
+    let quota_ip = validate_creation_address(&req, &config)?;
+
+    let challenge = validate_challenge(
+        &config,
+        params.challenge.as_deref(),
+        params.solution.as_deref(),
+    )?;
+
+    let nickname = Nickname::validate(&config, &app_data.nickname_blocklist, &params.nickname)?;
+
+    let mut pg_client = pg_pool.get().await?;
+    let transaction = pg_client.transaction().await?;
+
+    if let Some(challenge) = &challenge {
+        use_challenge(&transaction, challenge).await?;
+    }
+
+    reserve_player_creation(&transaction, &config, quota_ip).await?;
+
+    let (player_id, uuid) = insert_player(&transaction, &nickname).await?;
+    let token = insert_player_token(&transaction, &config, player_id).await?;
 
-    if nickname.is_empty() {
+    let recovery_codes = if params.recovery_codes {
+        Some(generate_recovery_codes(&transaction, &config, player_id).await?)
+    } else {
+        None
+    };
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(CreatePlayerResponse {
+        uuid,
+        token,
+        recovery_codes,
+    }))
+}
+
+/// Marks a solved challenge as used, a challenge can only create one player
+pub async fn use_challenge(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    challenge: &Challenge,
+) -> Result<(), RouteError> {
+    let use_challenge_statement = transaction
+        .prepare_typed_cached(
+            "INSERT INTO player_creation_challenges(nonce, expire_timestamp) VALUES($1, $2) ON CONFLICT DO NOTHING",
+            &[Type::BYTEA, Type::INT8],
+        )
+        .await?;
+
+    if transaction
+        .execute(
+            &use_challenge_statement,
+            &[
+                &challenge.nonce.as_slice(),
+                &(challenge.expire_timestamp as i64),
+            ],
+        )
+        .await?
+        == 0
+    {
         return Err(RouteError::InvalidRequest(
-            ServerErrorCode::NicknameEmpty,
-            "Nickname cannot be empty".to_string(),
+            ServerErrorCode::InvalidChallenge,
+            "Challenge has already been used".to_string(),
         ));
     }
 
-    if nickname.len() > config.player_nickname_maxlength {
+    Ok(())
+}
+
+/// Counts a player creation from this address, if its hourly and daily limits allow it
+///
+/// The creations from the same address are serialized until the end of the transaction, so
+/// concurrent requests can't all pass the limits before any of them is counted.
+pub async fn reserve_player_creation(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    config: &ApiConfig,
+    quota_ip: IpAddr,
+) -> Result<(), RouteError> {
+    let lock_player_creations_statement = transaction
+        .prepare_typed_cached(
+            "SELECT pg_advisory_xact_lock(hashtext(host($1)))",
+            &[Type::INET],
+        )
+        .await?;
+
+    let count_player_creations_statement = transaction
+        .prepare_typed_cached(
+            "SELECT COUNT(*) FILTER (WHERE creation_time > NOW() - INTERVAL '1 hour'), COUNT(*) FROM player_creations WHERE ip = $1 AND creation_time > NOW() - INTERVAL '1 day'",
+            &[Type::INET],
+        )
+        .await?;
+
+    // the creation which has to leave the window for the count to be under the limit
+    let find_limiting_creation_statement = transaction
+        .prepare_typed_cached(
+            "SELECT CEIL(EXTRACT(EPOCH FROM creation_time + make_interval(secs => $2) - NOW()))::int8 FROM player_creations WHERE ip = $1 AND creation_time > NOW() - make_interval(secs => $2) ORDER BY creation_time OFFSET $3 LIMIT 1",
+            &[Type::INET, Type::FLOAT8, Type::INT8],
+        )
+        .await?;
+
+    let insert_player_creation_statement = transaction
+        .prepare_typed_cached(
+            "INSERT INTO player_creations(ip, creation_time) VALUES($1, NOW())",
+            &[Type::INET],
+        )
+        .await?;
+
+    transaction
+        .execute(&lock_player_creations_statement, &[&quota_ip])
+        .await?;
+
+    let creations_result = transaction
+        .query_one(&count_player_creations_statement, &[&quota_ip])
+        .await?;
+
+    let hourly_creations: i64 = creations_result.try_get(0)?;
+    let daily_creations: i64 = creations_result.try_get(1)?;
+
+    let mut retry_after = None;
+    for (limit, creations, window) in [
+        (
+            config.player_creation_hourly_limit,
+            hourly_creations,
+            60 * 60,
+        ),
+        (
+            config.player_creation_daily_limit,
+            daily_creations,
+            24 * 60 * 60,
+        ),
+    ] {
+        let Some(limit) = limit.map(i64::from).filter(|&limit| creations >= limit) else {
+            continue;
+        };
+
+        // a limit of 0 has no creation to wait for, the whole window is given
+        let wait = transaction
+            .query_opt(
+                &find_limiting_creation_statement,
+                &[&quota_ip, &(window as f64), &(creations - limit)],
+            )
+            .await?
+            .map(|row| row.try_get::<_, i64>(0))
+            .transpose()?
+            .unwrap_or(window);
+
+        retry_after = retry_after.max(Some(wait.max(0) as u64));
+    }
+
+    if let Some(retry_after) = retry_after {
         return Err(RouteError::InvalidRequest(
-            ServerErrorCode::NicknameToolong,
-            format!(
-                "Nickname size exceeds maximum size of {}",
-                config.player_nickname_maxlength
-            ),
+            ServerErrorCode::PlayerCreationLimitReached(retry_after),
+            format!("Player creation limit reached for {quota_ip}"),
         ));
     }
 
-    if !config.player_allow_non_ascii {
-        if let Some(char) = nickname
-            .chars()
-            .find(|&x| !x.is_ascii_alphanumeric() && x != ' ' && x != '_')
-        {
-            return Err(RouteError::InvalidRequest(
-                ServerErrorCode::NicknameForbiddenCharacters,
-                format!("Nickname can only have ascii characters (invalid character {char})"),
-            ));
-        }
-    }
+    transaction
+        .execute(&insert_player_creation_statement, &[&quota_ip])
+        .await?;
 
-    let uuid = Uuid::new_v4();
+    Ok(())
+}
 
-    let mut pg_client = pg_pool.get().await?;
+/// Creates a player with an already validated nickname, returns its id and uuid
+pub async fn insert_player(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    nickname: &Nickname,
+) -> Result<(i32, Uuid), RouteError> {
+    let find_reserved_nickname_statement = transaction
+        .prepare_typed_cached(
+            "SELECT 1 FROM player_nickname_history WHERE nickname_key = $1 AND reserved_until > NOW()",
+            &[Type::VARCHAR],
+        )
+        .await?;
 
-    let create_player_statement = pg_client
+    let create_player_statement = transaction
         .prepare_typed_cached(
-            "INSERT INTO players(uuid, creation_time, nickname) VALUES($1, NOW(), $2) RETURNING id",
-            &[Type::UUID, Type::VARCHAR],
+            "INSERT INTO players(uuid, creation_time, nickname, nickname_key) VALUES($1, NOW(), $2, $3) RETURNING id",
+            &[Type::UUID, Type::VARCHAR, Type::VARCHAR],
         )
         .await?;
 
-    let create_token_statement = pg_client
+    if transaction
+        .query_opt(&find_reserved_nickname_statement, &[&nickname.key])
+        .await?
+        .is_some()
+    {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::NicknameReserved,
+            format!(
+                "Nickname '{}' has recently been used and is reserved",
+                nickname.display
+            ),
+        ));
+    }
+
+    let uuid = Uuid::new_v4();
+
+    let created_player_result = transaction
+        .query_one(
+            &create_player_statement,
+            &[&uuid, &nickname.display, &nickname.key],
+        )
+        .await
+        .map_err(|err| nickname_conflict(err, nickname))?;
+
+    Ok((created_player_result.try_get(0)?, uuid))
+}
+
+/// Gives a new token to a player, without device name
+pub async fn insert_player_token(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    config: &ApiConfig,
+    player_id: i32,
+) -> Result<Token, RouteError> {
+    let create_token_statement = transaction
         .prepare_typed_cached(
-            "INSERT INTO player_tokens(token, player_id) VALUES($1, $2)",
-            &[Type::VARCHAR, Type::INT4],
+            "INSERT INTO player_tokens(token_hash, player_id, expires_at) VALUES($1, $2, NOW() + make_interval(secs => $3))",
+            &[Type::BYTEA, Type::INT4, Type::FLOAT8],
         )
         .await?;
 
@@ -84,20 +390,185 @@ async fn create(
         ));
     };
 
-    let transaction = pg_client.transaction().await?;
-    let created_player_result = transaction
-        .query_one(&create_player_statement, &[&uuid, &nickname])
+    transaction
+        .execute(
+            &create_token_statement,
+            &[
+                &token.hash(config.player_token_pepper.unsecure().as_bytes()),
+                &player_id,
+                &config.player_token_duration.map(|d| d.as_secs_f64()),
+            ],
+        )
         .await?;
 
-    let player_id: i32 = created_player_result.try_get(0)?;
+    Ok(token)
+}
+
+#[derive(Deserialize)]
+struct RenamePlayerParams {
+    token: String,
+    nickname: String,
+}
+
+#[derive(Serialize)]
+struct RenamePlayerResponse {
+    nickname: String,
+}
+
+#[post("/v1/player/rename")]
+async fn rename(
+    app_data: web::Data<AppData>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<RenamePlayerParams>,
+) -> Result<impl Responder, RouteError> {
+    let nickname = Nickname::validate(&config, &app_data.nickname_blocklist, &params.nickname)?;
+
+    let mut pg_client = pg_pool.get().await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
+
+    let find_player_nickname_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT nickname, nickname_key, (SELECT EXTRACT(EPOCH FROM NOW() - MAX(change_time))::int8 FROM player_nickname_history WHERE player_id = $1) FROM players WHERE id = $1 FOR UPDATE",
+            &[Type::INT4],
+        )
+        .await?;
+
+    // a player can take back one of its own previous nicknames
+    let find_reserved_nickname_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT 1 FROM player_nickname_history WHERE nickname_key = $1 AND reserved_until > NOW() AND player_id <> $2",
+            &[Type::VARCHAR, Type::INT4],
+        )
+        .await?;
+
+    let rename_player_statement = pg_client
+        .prepare_typed_cached(
+            "UPDATE players SET nickname = $1, nickname_key = $2 WHERE id = $3",
+            &[Type::VARCHAR, Type::VARCHAR, Type::INT4],
+        )
+        .await?;
+
+    let insert_nickname_history_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO player_nickname_history(player_id, nickname, nickname_key, change_time, reserved_until) VALUES($1, $2, $3, NOW(), NOW() + make_interval(secs => $4))",
+            &[Type::INT4, Type::VARCHAR, Type::VARCHAR, Type::FLOAT8],
+        )
+        .await?;
+
+    let transaction = pg_client.transaction().await?;
+
+    let player_result = transaction
+        .query_opt(&find_player_nickname_statement, &[&player_id])
+        .await?
+        .ok_or(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidId,
+            format!("No player has the id '{player_id}'"),
+        ))?;
+
+    let previous_nickname: String = player_result.try_get(0)?;
+    let previous_nickname_key: String = player_result.try_get(1)?;
+    let last_change_elapsed: Option<i64> = player_result.try_get(2)?;
+
+    if previous_nickname == nickname.display {
+        return Ok(HttpResponse::Ok().json(RenamePlayerResponse {
+            nickname: previous_nickname,
+        }));
+    }
+
+    let cooldown = config.player_nickname_change_cooldown.as_secs() as i64;
+    if let Some(elapsed) = last_change_elapsed
+        && elapsed < cooldown
+    {
+        let remaining = cooldown - elapsed;
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::NicknameChangeCooldown(remaining),
+            format!("Nickname can only be changed again in {remaining} seconds"),
+        ));
+    }
+
+    if transaction
+        .query_opt(
+            &find_reserved_nickname_statement,
+            &[&nickname.key, &player_id],
+        )
+        .await?
+        .is_some()
+    {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::NicknameReserved,
+            format!(
+                "Nickname '{}' has recently been used and is reserved",
+                nickname.display
+            ),
+        ));
+    }
+
+    transaction
+        .execute(
+            &rename_player_statement,
+            &[&nickname.display, &nickname.key, &player_id],
+        )
+        .await
+        .map_err(|err| nickname_conflict(err, &nickname))?;
 
     transaction
-        .execute(&create_token_statement, &[&token, &player_id])
+        .execute(
+            &insert_nickname_history_statement,
+            &[
+                &player_id,
+                &previous_nickname,
+                &previous_nickname_key,
+                &config.player_nickname_reservation.map(|d| d.as_secs_f64()),
+            ],
+        )
         .await?;
 
     transaction.commit().await?;
 
-    Ok(HttpResponse::Ok().json(CreatePlayerResponse { uuid, token }))
+    Ok(HttpResponse::Ok().json(RenamePlayerResponse {
+        nickname: nickname.display,
+    }))
+}
+
+#[derive(Deserialize)]
+struct NicknameAvailabilityQuery {
+    nickname: String,
+}
+
+#[derive(Serialize)]
+struct NicknameAvailabilityResponse {
+    nickname: String,
+    available: bool,
+}
+
+#[get("/v1/players/nickname_available")]
+async fn nickname_available(
+    app_data: web::Data<AppData>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    query: web::Query<NicknameAvailabilityQuery>,
+) -> Result<impl Responder, RouteError> {
+    let nickname = Nickname::validate(&config, &app_data.nickname_blocklist, &query.nickname)?;
+
+    let pg_client = pg_pool.get().await?;
+
+    let find_nickname_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT EXISTS(SELECT 1 FROM players WHERE nickname_key = $1) OR EXISTS(SELECT 1 FROM player_nickname_history WHERE nickname_key = $1 AND reserved_until > NOW())",
+            &[Type::VARCHAR],
+        )
+        .await?;
+
+    let taken: bool = pg_client
+        .query_one(&find_nickname_statement, &[&nickname.key])
+        .await?
+        .try_get(0)?;
+
+    Ok(HttpResponse::Ok().json(NicknameAvailabilityResponse {
+        nickname: nickname.display,
+        available: !taken,
+    }))
 }
 
 #[derive(Deserialize)]
@@ -114,10 +585,11 @@ struct AuthenticationResponse {
 #[post("/v1/player/auth")]
 async fn auth(
     pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
     params: web::Json<AuthenticationParams>,
 ) -> Result<impl Responder, RouteError> {
     let pg_client = pg_pool.get().await?;
-    let player_id = validate_player_token(&pg_client, &params.token).await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
 
     let find_player_info = pg_client
         .prepare_typed_cached(
@@ -143,10 +615,118 @@ async fn auth(
     }))
 }
 
+/// Transforms the unique violation of a nickname into a proper error
+fn nickname_conflict(err: tokio_postgres::Error, nickname: &Nickname) -> RouteError {
+    if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+        return RouteError::InvalidRequest(
+            ServerErrorCode::NicknameTaken,
+            format!("Nickname '{}' is already taken", nickname.display),
+        );
+    }
+
+    err.into()
+}
+
 pub async fn validate_player_token(
     pg_client: &deadpool_postgres::Client,
+    config: &ApiConfig,
     token: &str,
 ) -> Result<i32, RouteError> {
+    let player_token = find_player_token(pg_client, config, token).await?;
+
+    validate_player_state(
+        pg_client,
+        player_token.player_id,
+        player_token.pending_deletion,
+    )
+    .await?;
+
+    Ok(player_token.player_id)
+}
+
+/// Refuses a player pending deletion or banned, as [`validate_player_token`] does for player tokens
+pub async fn validate_player(
+    client: &impl GenericClient,
+    player_id: i32,
+) -> Result<(), RouteError> {
+    let find_player_statement = client
+        .prepare_typed_cached(
+            "SELECT deletion_time IS NOT NULL FROM players WHERE id = $1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let pending_deletion: bool = client
+        .query_one(&find_player_statement, &[&player_id])
+        .await?
+        .try_get(0)?;
+
+    validate_player_state(client, player_id, pending_deletion).await
+}
+
+async fn validate_player_state(
+    client: &impl GenericClient,
+    player_id: i32,
+    pending_deletion: bool,
+) -> Result<(), RouteError> {
+    if pending_deletion {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::PlayerPendingDeletion,
+            format!("Player {player_id} is pending deletion, cancel it to use the account again"),
+        ));
+    }
+
+    if let Some(ban) = find_active_ban(client, player_id).await? {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::PlayerBanned {
+                reason: ban.reason,
+                expire_timestamp: ban.expire_timestamp,
+            },
+            format!("Player {player_id} is banned"),
+        ));
+    }
+
+    Ok(())
+}
+
+pub struct PlayerBan {
+    pub reason: String,
+    pub expire_timestamp: Option<i64>,
+}
+
+/// Finds the ban currently applied to the player, the longest one if there are several
+pub async fn find_active_ban(
+    client: &impl GenericClient,
+    player_id: i32,
+) -> Result<Option<PlayerBan>, RouteError> {
+    let find_ban_statement = client
+        .prepare_typed_cached(
+            "SELECT reason, EXTRACT(EPOCH FROM end_time)::int8 FROM player_bans WHERE player_id = $1 AND start_time <= NOW() AND (end_time IS NULL OR end_time > NOW()) ORDER BY end_time DESC NULLS FIRST LIMIT 1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let Some(ban_result) = client.query_opt(&find_ban_statement, &[&player_id]).await? else {
+        return Ok(None);
+    };
+
+    Ok(Some(PlayerBan {
+        reason: ban_result.try_get(0)?,
+        expire_timestamp: ban_result.try_get(1)?,
+    }))
+}
+
+pub struct PlayerToken {
+    pub player_id: i32,
+    pub pending_deletion: bool,
+}
+
+/// Checks the token without checking the state of the player it belongs to, prefer [`validate_player_token`]
+pub async fn find_player_token(
+    pg_client: &deadpool_postgres::Client,
+    config: &ApiConfig,
+    token: &str,
+) -> Result<PlayerToken, RouteError> {
     if token.is_empty() {
         return Err(RouteError::InvalidRequest(
             ServerErrorCode::EmptyToken,
@@ -159,30 +739,104 @@ pub async fn validate_player_token(
             ServerErrorCode::InvalidToken(Some(token.to_string())),
             format!(
                 "The given token '...{}' is invalid (too long)",
-                &token[token.len() - 6..token.len()]
+                token_suffix(token)
             ),
         ));
     }
 
+    let token_hash = hash_token(config.player_token_pepper.unsecure().as_bytes(), token);
+
     let find_token_statement = pg_client
         .prepare_typed_cached(
-            "SELECT player_id FROM player_tokens WHERE token = $1",
-            &[Type::VARCHAR],
+            "SELECT t.id, t.player_id, COALESCE(t.expires_at < NOW(), FALSE), EXTRACT(EPOCH FROM NOW() - COALESCE(t.last_used_at, t.creation_time))::int8, p.deletion_time IS NOT NULL FROM player_tokens t JOIN players p ON p.id = t.player_id WHERE t.token_hash = $1",
+            &[Type::BYTEA],
         )
         .await?;
 
-    let token_result = pg_client
-        .query_opt(&find_token_statement, &[&token])
+    let token_result = match pg_client
+        .query_opt(&find_token_statement, &[&token_hash])
         .await?
-        .ok_or(RouteError::InvalidRequest(
-            ServerErrorCode::AuthenticationInvalidToken(token.to_string()),
+    {
+        Some(token_result) => Some(token_result),
+        None => {
+            // Tokens created before they were hashed are still stored in plaintext, upgrade them on the fly
+            let upgrade_token_statement = pg_client
+                .prepare_typed_cached(
+                    "UPDATE player_tokens t SET token = NULL, token_hash = $1 FROM players p WHERE p.id = t.player_id AND t.token = $2 RETURNING t.id, t.player_id, COALESCE(t.expires_at < NOW(), FALSE), EXTRACT(EPOCH FROM NOW() - COALESCE(t.last_used_at, t.creation_time))::int8, p.deletion_time IS NOT NULL",
+                    &[Type::BYTEA, Type::VARCHAR],
+                )
+                .await?;
+
+            pg_client
+                .query_opt(&upgrade_token_statement, &[&token_hash, &token])
+                .await?
+        }
+    }
+    .ok_or(RouteError::InvalidRequest(
+        ServerErrorCode::AuthenticationInvalidToken(token.to_string()),
+        format!(
+            "No player has the token which ends with '...{}'",
+            token_suffix(token)
+        ),
+    ))?;
+
+    let token_id: i32 = token_result.try_get(0)?;
+    let player_id: i32 = token_result.try_get(1)?;
+    let expired: bool = token_result.try_get(2)?;
+    let idle_time: i64 = token_result.try_get(3)?;
+    let pending_deletion: bool = token_result.try_get(4)?;
+
+    let idle_expired = config
+        .player_token_idle_expiry
+        .is_some_and(|idle_expiry| idle_time > idle_expiry.as_secs() as i64);
+
+    if expired || idle_expired {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::ExpiredToken(token.to_string()),
             format!(
-                "No player has the token which ends with '...{}'",
-                &token[token.len() - 6..token.len()]
+                "The token which ends with '...{}' has expired",
+                token_suffix(token)
             ),
-        ))?;
+        ));
+    }
 
-    Ok(token_result.try_get(0)?)
+    // Update last usage time in a separate task as its result won't affect the route
+    if let Some(pg_pool) = deadpool_postgres::Client::pool(pg_client) {
+        tokio::spawn(async move {
+            match pg_pool.get().await {
+                Ok(pg_client) => update_token_usage(&pg_client, token_id).await,
+                Err(err) => log::error!(
+                    "Failed to update token {token_id} usage time (failed to get a client): {err}"
+                ),
+            }
+        });
+    }
+
+    Ok(PlayerToken {
+        player_id,
+        pending_deletion,
+    })
+}
+
+async fn update_token_usage(pg_client: &deadpool_postgres::Client, token_id: i32) {
+    match pg_client
+        .prepare_typed_cached(
+            "UPDATE player_tokens SET last_used_at = NOW() WHERE id = $1",
+            &[Type::INT4],
+        )
+        .await
+    {
+        Ok(statement) => {
+            if let Err(err) = pg_client.execute(&statement, &[&token_id]).await {
+                log::error!("Failed to update token {token_id} usage time: {err}");
+            }
+        }
+        Err(err) => {
+            log::error!(
+                "Failed to update token {token_id} usage time (failed to prepare query): {err}"
+            );
+        }
+    }
 }
 
 async fn update_player_connection(pg_client: &deadpool_postgres::Client, player_id: i32) {
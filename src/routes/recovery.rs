@@ -0,0 +1,151 @@
+use actix_web::{HttpResponse, Responder, post, web};
+use deadpool_postgres::tokio_postgres::types::Type;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::ApiConfig;
+use crate::data::recovery_code;
+use crate::data::token::{Token, hash_token};
+use crate::errors::api::{ErrorCause, RouteError};
+use crate::errors::codes::ServerErrorCode;
+use crate::routes::players::{insert_player_token, validate_player_token};
+
+/// Replaces the recovery codes of a player, only their hashes are stored
+pub async fn generate_recovery_codes(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    config: &ApiConfig,
+    player_id: i32,
+) -> Result<Vec<String>, RouteError> {
+    let delete_recovery_codes_statement = transaction
+        .prepare_typed_cached(
+            "DELETE FROM player_recovery_codes WHERE player_id = $1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let insert_recovery_code_statement = transaction
+        .prepare_typed_cached(
+            "INSERT INTO player_recovery_codes(player_id, code_hash, creation_time) VALUES($1, $2, NOW())",
+            &[Type::INT4, Type::BYTEA],
+        )
+        .await?;
+
+    transaction
+        .execute(&delete_recovery_codes_statement, &[&player_id])
+        .await?;
+
+    let mut codes = Vec::with_capacity(config.player_recovery_code_count);
+    for _ in 0..config.player_recovery_code_count {
+        let Ok(code) = recovery_code::generate(OsRng) else {
+            return Err(RouteError::ServerError(
+                ErrorCause::Internal,
+                ServerErrorCode::TokenGenerationFailed,
+            ));
+        };
+
+        let code_hash = hash_token(
+            config.player_token_pepper.unsecure().as_bytes(),
+            &recovery_code::normalize(&code),
+        );
+
+        transaction
+            .execute(&insert_recovery_code_statement, &[&player_id, &code_hash])
+            .await?;
+
+        codes.push(code);
+    }
+
+    Ok(codes)
+}
+
+#[derive(Deserialize)]
+struct RecoveryCodesParams {
+    token: String,
+}
+
+#[derive(Serialize)]
+struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+#[post("/v1/player/recovery_codes")]
+async fn regenerate_recovery_codes(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<RecoveryCodesParams>,
+) -> Result<impl Responder, RouteError> {
+    let mut pg_client = pg_pool.get().await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
+
+    let transaction = pg_client.transaction().await?;
+    let recovery_codes = generate_recovery_codes(&transaction, &config, player_id).await?;
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+#[derive(Deserialize)]
+struct RecoverParams {
+    recovery_code: String,
+}
+
+#[derive(Serialize)]
+struct RecoverResponse {
+    uuid: Uuid,
+    token: Token,
+}
+
+/// Exchanges a recovery code for a new token, the other tokens of the player are revoked
+#[post("/v1/player/recover")]
+async fn recover(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<RecoverParams>,
+) -> Result<impl Responder, RouteError> {
+    let mut pg_client = pg_pool.get().await?;
+
+    let use_recovery_code_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM player_recovery_codes c USING players p WHERE c.code_hash = $1 AND p.id = c.player_id RETURNING p.id, p.uuid",
+            &[Type::BYTEA],
+        )
+        .await?;
+
+    let delete_tokens_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM player_tokens WHERE player_id = $1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let code_hash = hash_token(
+        config.player_token_pepper.unsecure().as_bytes(),
+        &recovery_code::normalize(&params.recovery_code),
+    );
+
+    let transaction = pg_client.transaction().await?;
+
+    let player_result = transaction
+        .query_opt(&use_recovery_code_statement, &[&code_hash])
+        .await?
+        .ok_or(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidRecoveryCode,
+            "Unknown or already used recovery code".to_string(),
+        ))?;
+
+    let player_id: i32 = player_result.try_get(0)?;
+    let uuid: Uuid = player_result.try_get(1)?;
+
+    let revoked = transaction
+        .execute(&delete_tokens_statement, &[&player_id])
+        .await?;
+
+    let token = insert_player_token(&transaction, &config, player_id).await?;
+
+    transaction.commit().await?;
+
+    log::info!("Player {player_id} recovered its account, {revoked} tokens revoked");
+
+    Ok(HttpResponse::Ok().json(RecoverResponse { uuid, token }))
+}
@@ -0,0 +1,286 @@
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, post, web};
+use chacha20poly1305::XChaCha20Poly1305;
+use chacha20poly1305::aead::{KeyInit, OsRng};
+use deadpool_postgres::tokio_postgres::types::Type;
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+
+use crate::config::ApiConfig;
+use crate::errors::api::{ErrorCause, RouteError};
+use crate::errors::codes::ServerErrorCode;
+use crate::routes::game_server::validate_game_server;
+
+const MAX_FIELD_LENGTH: usize = 255;
+
+/// Game server chosen to receive a player
+pub struct SelectedGameServer {
+    /// unset for the server of the config (`game_server_address`)
+    pub game_server_id: Option<i32>,
+    pub address: String,
+    pub port: u16,
+    pub connection_token_key: chacha20poly1305::Key,
+}
+
+/// Chooses the least loaded running game server having room for `player_count` more players
+///
+/// The choice can be restricted to a region or to a given server. The server of the config is
+/// only used if no game server is registered and no restriction is requested.
+pub async fn select_game_server(
+    pg_client: &deadpool_postgres::Client,
+    config: &ApiConfig,
+    region: Option<&str>,
+    game_server_id: Option<i32>,
+    player_count: i32,
+) -> Result<SelectedGameServer, RouteError> {
+    // the players are counted right away so the next players don't all go to the same server
+    // before its next heartbeat
+    let select_game_server_statement = pg_client
+        .prepare_typed_cached(
+            "UPDATE game_server_registrations SET player_count = player_count + $4 WHERE game_server_id = (SELECT game_server_id FROM game_server_registrations WHERE last_heartbeat > NOW() - make_interval(secs => $1) AND player_count + $4 <= capacity AND ($2::int4 IS NULL OR game_server_id = $2) AND ($3::varchar IS NULL OR region = $3) ORDER BY player_count::float8 / capacity, player_count LIMIT 1) RETURNING game_server_id, address, port, connection_token_key",
+            &[Type::FLOAT8, Type::INT4, Type::VARCHAR, Type::INT4],
+        )
+        .await?;
+
+    let any_game_server_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT EXISTS(SELECT 1 FROM game_server_registrations WHERE last_heartbeat > NOW() - make_interval(secs => $1))",
+            &[Type::FLOAT8],
+        )
+        .await?;
+
+    let heartbeat_timeout = config.game_server_heartbeat_timeout.as_secs_f64();
+
+    let Some(row) = pg_client
+        .query_opt(
+            &select_game_server_statement,
+            &[&heartbeat_timeout, &game_server_id, &region, &player_count],
+        )
+        .await?
+    else {
+        let any_game_server: bool = pg_client
+            .query_one(&any_game_server_statement, &[&heartbeat_timeout])
+            .await?
+            .try_get(0)?;
+
+        if region.is_none() && game_server_id.is_none() && !any_game_server {
+            return Ok(SelectedGameServer {
+                game_server_id: None,
+                address: config.game_server_address.clone(),
+                port: config.game_server_port,
+                connection_token_key: config.connection_token_key.into(),
+            });
+        }
+
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::NoServerAvailable,
+            format!("No game server available (region: {region:?}, server: {game_server_id:?})"),
+        ));
+    };
+
+    let game_server_id: i32 = row.try_get(0)?;
+    let port: i32 = row.try_get(2)?;
+    let connection_token_key: Vec<u8> = row.try_get(3)?;
+
+    let (Ok(port), Ok(connection_token_key)) = (
+        u16::try_from(port),
+        <[u8; 32]>::try_from(connection_token_key.as_slice()),
+    ) else {
+        return Err(RouteError::ServerError(
+            ErrorCause::Internal,
+            ServerErrorCode::External(format!(
+                "Game server {game_server_id} has an invalid registration"
+            )),
+        ));
+    };
+
+    Ok(SelectedGameServer {
+        game_server_id: Some(game_server_id),
+        address: row.try_get(1)?,
+        port,
+        connection_token_key: connection_token_key.into(),
+    })
+}
+
+#[derive(Deserialize)]
+struct RegisterParams {
+    address: String,
+    port: u16,
+    region: String,
+    capacity: u16,
+    version: String,
+    player_count: u16,
+}
+
+#[serde_as]
+#[derive(Serialize)]
+struct RegisterResponse {
+    /// seconds between two heartbeats to stay registered
+    heartbeat_interval: u64,
+    /// key decrypting the connection tokens of this server, until it registers again
+    #[serde_as(as = "Base64")]
+    connection_token_key: chacha20poly1305::Key,
+}
+
+#[derive(Serialize)]
+struct HeartbeatResponse {
+    heartbeat_interval: u64,
+}
+
+/// Registers the running game server so players can be sent to it
+///
+/// Registering again replaces the previous registration (after a restart or an eviction) and its
+/// connection token key, so tokens issued for a previous registration can't be used anymore.
+#[post("/game_server/v1/register")]
+async fn register(
+    req: HttpRequest,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    params: web::Json<RegisterParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let game_server_id = validate_game_server(&req, &pg_client, &config).await?;
+
+    validate_field("address", &params.address)?;
+    validate_field("region", &params.region)?;
+    validate_field("version", &params.version)?;
+
+    let register_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO game_server_registrations(game_server_id, address, port, region, capacity, version, player_count, connection_token_key, registration_time, last_heartbeat) VALUES($1, $2, $3, $4, $5, $6, $7, $8, NOW(), NOW()) ON CONFLICT(game_server_id) DO UPDATE SET address = EXCLUDED.address, port = EXCLUDED.port, region = EXCLUDED.region, capacity = EXCLUDED.capacity, version = EXCLUDED.version, player_count = EXCLUDED.player_count, connection_token_key = EXCLUDED.connection_token_key, registration_time = NOW(), last_heartbeat = NOW()",
+            &[
+                Type::INT4,
+                Type::VARCHAR,
+                Type::INT4,
+                Type::VARCHAR,
+                Type::INT4,
+                Type::VARCHAR,
+                Type::INT4,
+                Type::BYTEA,
+            ],
+        )
+        .await?;
+
+    let connection_token_key = XChaCha20Poly1305::generate_key(OsRng);
+
+    pg_client
+        .execute(
+            &register_statement,
+            &[
+                &game_server_id,
+                &params.address,
+                &i32::from(params.port),
+                &params.region,
+                &i32::from(params.capacity),
+                &params.version,
+                &i32::from(params.player_count),
+                &connection_token_key.as_slice(),
+            ],
+        )
+        .await?;
+
+    log::info!(
+        "Game server {game_server_id} registered at {}:{} ({}, version {})",
+        params.address,
+        params.port,
+        params.region,
+        params.version
+    );
+
+    Ok(HttpResponse::Ok().json(RegisterResponse {
+        heartbeat_interval: heartbeat_interval(&config),
+        connection_token_key,
+    }))
+}
+
+#[derive(Deserialize)]
+struct HeartbeatParams {
+    player_count: u16,
+}
+
+#[post("/game_server/v1/heartbeat")]
+async fn heartbeat(
+    req: HttpRequest,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    params: web::Json<HeartbeatParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let game_server_id = validate_game_server(&req, &pg_client, &config).await?;
+
+    // a server which missed its heartbeats has to register again, even if it wasn't evicted yet
+    let heartbeat_statement = pg_client
+        .prepare_typed_cached(
+            "UPDATE game_server_registrations SET player_count = $2, last_heartbeat = NOW() WHERE game_server_id = $1 AND last_heartbeat > NOW() - make_interval(secs => $3)",
+            &[Type::INT4, Type::INT4, Type::FLOAT8],
+        )
+        .await?;
+
+    let updated = pg_client
+        .execute(
+            &heartbeat_statement,
+            &[
+                &game_server_id,
+                &i32::from(params.player_count),
+                &config.game_server_heartbeat_timeout.as_secs_f64(),
+            ],
+        )
+        .await?;
+
+    if updated == 0 {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::GameServerNotRegistered,
+            format!("Game server {game_server_id} isn't registered"),
+        ));
+    }
+
+    Ok(HttpResponse::Ok().json(HeartbeatResponse {
+        heartbeat_interval: heartbeat_interval(&config),
+    }))
+}
+
+/// Unregisters the game server when it shuts down, instead of waiting for its eviction
+#[delete("/game_server/v1/register")]
+async fn unregister(
+    req: HttpRequest,
+    config: web::Data<ApiConfig>,
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let game_server_id = validate_game_server(&req, &pg_client, &config).await?;
+
+    let unregister_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM game_server_registrations WHERE game_server_id = $1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    if pg_client
+        .execute(&unregister_statement, &[&game_server_id])
+        .await?
+        > 0
+    {
+        log::info!("Game server {game_server_id} unregistered");
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn heartbeat_interval(config: &ApiConfig) -> u64 {
+    // leaves room for a missed heartbeat before the eviction
+    (config.game_server_heartbeat_timeout.as_secs() / 3).max(1)
+}
+
+fn validate_field(field: &str, value: &str) -> Result<(), RouteError> {
+    if value.is_empty()
+        || value.len() > MAX_FIELD_LENGTH
+        || value.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidGameServerRegistration,
+            format!("Game server {field} '{value}' is invalid"),
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,277 @@
+use actix_web::{HttpResponse, Responder, post, web};
+use deadpool_postgres::tokio_postgres::types::Type;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ApiConfig;
+use crate::data::token::{Token, hash_token};
+use crate::errors::api::{ErrorCause, RouteError};
+use crate::errors::codes::ServerErrorCode;
+use crate::routes::players::validate_player_token;
+
+const DEVICE_NAME_MAXLENGTH: usize = 64;
+
+#[derive(Deserialize)]
+struct CreateTokenParams {
+    token: String,
+    device_name: String,
+}
+
+#[derive(Serialize)]
+struct CreateTokenResponse {
+    id: i32,
+    token: Token,
+}
+
+#[post("/v1/player/token/create")]
+async fn create_token(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<CreateTokenParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
+
+    let device_name = params.device_name.trim();
+    if device_name.is_empty() || device_name.chars().count() > DEVICE_NAME_MAXLENGTH {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidDeviceName,
+            format!("Device name must be between 1 and {DEVICE_NAME_MAXLENGTH} characters"),
+        ));
+    }
+
+    let create_token_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO player_tokens(token_hash, player_id, device_name, expires_at) VALUES($1, $2, $3, NOW() + make_interval(secs => $4)) RETURNING id",
+            &[Type::BYTEA, Type::INT4, Type::VARCHAR, Type::FLOAT8],
+        )
+        .await?;
+
+    let Ok(token) = Token::generate(OsRng) else {
+        return Err(RouteError::ServerError(
+            ErrorCause::Internal,
+            ServerErrorCode::TokenGenerationFailed,
+        ));
+    };
+
+    let created_token_result = pg_client
+        .query_one(
+            &create_token_statement,
+            &[
+                &token.hash(config.player_token_pepper.unsecure().as_bytes()),
+                &player_id,
+                &device_name,
+                &config.player_token_duration.map(|d| d.as_secs_f64()),
+            ],
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(CreateTokenResponse {
+        id: created_token_result.try_get(0)?,
+        token,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ListTokensParams {
+    token: String,
+}
+
+#[derive(Serialize)]
+struct TokenInfo {
+    id: i32,
+    device_name: Option<String>,
+    creation_timestamp: i64,
+    last_used_timestamp: Option<i64>,
+    expire_timestamp: Option<i64>,
+    current: bool,
+}
+
+#[post("/v1/player/token/list")]
+async fn list_tokens(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<ListTokensParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
+
+    let token_hash = hash_token(
+        config.player_token_pepper.unsecure().as_bytes(),
+        &params.token,
+    );
+
+    let list_tokens_statement = pg_client
+        .prepare_typed_cached(
+            "SELECT id, device_name, EXTRACT(EPOCH FROM creation_time)::int8, EXTRACT(EPOCH FROM last_used_at)::int8, EXTRACT(EPOCH FROM expires_at)::int8, COALESCE(token_hash = $2, FALSE) FROM player_tokens WHERE player_id = $1 ORDER BY creation_time",
+            &[Type::INT4, Type::BYTEA],
+        )
+        .await?;
+
+    let tokens = pg_client
+        .query(&list_tokens_statement, &[&player_id, &token_hash])
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(TokenInfo {
+                id: row.try_get(0)?,
+                device_name: row.try_get(1)?,
+                creation_timestamp: row.try_get(2)?,
+                last_used_timestamp: row.try_get(3)?,
+                expire_timestamp: row.try_get(4)?,
+                current: row.try_get(5)?,
+            })
+        })
+        .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[derive(Deserialize)]
+struct RevokeTokenParams {
+    token: String,
+    id: i32,
+}
+
+#[post("/v1/player/token/revoke")]
+async fn revoke_token(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<RevokeTokenParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
+
+    let delete_token_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM player_tokens WHERE id = $1 AND player_id = $2",
+            &[Type::INT4, Type::INT4],
+        )
+        .await?;
+
+    let deleted = pg_client
+        .execute(&delete_token_statement, &[&params.id, &player_id])
+        .await?;
+
+    if deleted == 0 {
+        return Err(RouteError::InvalidRequest(
+            ServerErrorCode::InvalidId,
+            format!("Player has no token with the id '{}'", params.id),
+        ));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize)]
+struct TokenRotationParams {
+    token: String,
+}
+
+#[derive(Serialize)]
+struct TokenRotationResponse {
+    token: Token,
+}
+
+#[post("/v1/player/token/rotate")]
+async fn rotate_token(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<TokenRotationParams>,
+) -> Result<impl Responder, RouteError> {
+    let mut pg_client = pg_pool.get().await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
+
+    let delete_token_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM player_tokens WHERE token_hash = $1 AND player_id = $2 RETURNING device_name",
+            &[Type::BYTEA, Type::INT4],
+        )
+        .await?;
+
+    let create_token_statement = pg_client
+        .prepare_typed_cached(
+            "INSERT INTO player_tokens(token_hash, player_id, device_name, expires_at) VALUES($1, $2, $3, NOW() + make_interval(secs => $4))",
+            &[Type::BYTEA, Type::INT4, Type::VARCHAR, Type::FLOAT8],
+        )
+        .await?;
+
+    let Ok(token) = Token::generate(OsRng) else {
+        return Err(RouteError::ServerError(
+            ErrorCause::Internal,
+            ServerErrorCode::TokenGenerationFailed,
+        ));
+    };
+
+    let transaction = pg_client.transaction().await?;
+
+    // the token may have been rotated or revoked by a concurrent request since its validation
+    let deleted_token_result = transaction
+        .query_opt(
+            &delete_token_statement,
+            &[
+                &hash_token(
+                    config.player_token_pepper.unsecure().as_bytes(),
+                    &params.token,
+                ),
+                &player_id,
+            ],
+        )
+        .await?
+        .ok_or(RouteError::InvalidRequest(
+            ServerErrorCode::AuthenticationInvalidToken(params.token.clone()),
+            "The token has been revoked".to_string(),
+        ))?;
+
+    // the new token stays linked to the same device
+    let device_name: Option<String> = deleted_token_result.try_get(0)?;
+
+    transaction
+        .execute(
+            &create_token_statement,
+            &[
+                &token.hash(config.player_token_pepper.unsecure().as_bytes()),
+                &player_id,
+                &device_name,
+                &config.player_token_duration.map(|d| d.as_secs_f64()),
+            ],
+        )
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(TokenRotationResponse { token }))
+}
+
+#[derive(Deserialize)]
+struct TokenRevocationParams {
+    token: String,
+}
+
+#[derive(Serialize)]
+struct TokenRevocationResponse {
+    revoked: u64,
+}
+
+#[post("/v1/player/token/revoke_all")]
+async fn revoke_tokens(
+    pg_pool: web::Data<deadpool_postgres::Pool>,
+    config: web::Data<ApiConfig>,
+    params: web::Json<TokenRevocationParams>,
+) -> Result<impl Responder, RouteError> {
+    let pg_client = pg_pool.get().await?;
+    let player_id = validate_player_token(&pg_client, &config, &params.token).await?;
+
+    let delete_tokens_statement = pg_client
+        .prepare_typed_cached(
+            "DELETE FROM player_tokens WHERE player_id = $1",
+            &[Type::INT4],
+        )
+        .await?;
+
+    let revoked = pg_client
+        .execute(&delete_tokens_statement, &[&player_id])
+        .await?;
+
+    Ok(HttpResponse::Ok().json(TokenRevocationResponse { revoked }))
+}
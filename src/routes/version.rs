@@ -27,7 +27,7 @@ async fn game_version(
     ver_query: web::Query<VersionQuery>,
 ) -> Result<impl Responder, RouteError> {
     let VersionQuery { platform } = ver_query.0;
-    let AppData { cache, fetcher } = app_data.as_ref();
+    let AppData { cache, fetcher, .. } = app_data.as_ref();
     let mut cache = cache.lock().await;
 
     // TODO: remove .cloned
@@ -68,16 +68,16 @@ async fn game_version(
 
     // remove the suffix (ex: -server) if any
     let mut updater_platform = platform.clone();
-    if updater_platform.contains('-') {
-        if let Some((platform, arch)) = updater_platform.split_once('_') {
-            updater_platform = format!(
-                "{}_{}",
-                platform
-                    .split_once('-')
-                    .map_or(platform, |(before, _after)| before),
-                arch
-            );
-        }
+    if updater_platform.contains('-')
+        && let Some((platform, arch)) = updater_platform.split_once('_')
+    {
+        updater_platform = format!(
+            "{}_{}",
+            platform
+                .split_once('-')
+                .map_or(platform, |(before, _after)| before),
+            arch
+        );
     }
 
     let updater_filename = format!("{}_{}", updater_platform, config.updater_filename);